@@ -1,20 +1,151 @@
-use std::{sync::{Mutex, Arc}, collections::HashMap, path::PathBuf};
-use rhai::CustomType;
+use std::{sync::{Mutex, Arc}, collections::{BTreeMap, BTreeSet}, path::{PathBuf, Path, Component}};
+use rhai::{CustomType, Dynamic, EvalAltResult};
+use colored::*;
+use sha2::{Sha256, Digest};
+
+use crate::{compiler::{PortIdentifier, NodesJSCompiler}, typescript::TsType, scope::VarScope};
+
+/// Comp time data key under which TypeScript type of an output is stored
+const TS_TYPE_DATA_KEY: &str = "__ts_type";
+/// Comp time data key under which the module to import TypeScript type from is stored
+const TS_IMPORT_DATA_KEY: &str = "__ts_import";
+
+/// First line of every generated file
+const GENERATED_HEADER: &str = "// This file is automatically generated by Disbotter";
+/// Prefix of the header line containing hash of the generated code
+const HASH_HEADER: &str = "// disbotter-hash: ";
+
+/// Name of the manifest file that lists all files owned by the generator in the output directory
+pub const MANIFEST_FILE: &str = ".disbotter-manifest.json";
+
+/// Manifest of generated files, used to find stale files on the next export
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct ProgramManifest {
+    /// Version of the generator that exported the files
+    pub version: String,
+    /// Paths of the generated files, relative to the output directory
+    pub files: Vec<String>,
+}
+
+impl ProgramManifest {
+    /// Reads the manifest from the output directory, returns empty manifest if there is none
+    pub fn read(output: &Path) -> Result<Self, ExportError> {
+        let path = output.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(&path).map_err(|e| ExportError::Io(path.clone(), e))?;
+        serde_json::from_str(&content).map_err(|e| ExportError::InvalidManifest(path, e))
+    }
 
-use crate::compiler::{PortIdentifier, NodesJSCompiler};
+    /// Writes the manifest to the output directory
+    pub fn write(&self, output: &Path) -> Result<(), ExportError> {
+        let path = output.join(MANIFEST_FILE);
+        let content = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(&path, content).map_err(|e| ExportError::Io(path, e))
+    }
+}
+
+/// All possible errors that can occur while exporting a program
+pub enum ExportError {
+    Io(PathBuf, std::io::Error),
+    InvalidManifest(PathBuf, serde_json::Error),
+    /// Generated files that were edited by hand since the last export
+    ModifiedFiles(Vec<String>),
+    /// Files that were not generated, but have the same path as a generated file
+    ForeignFiles(Vec<String>),
+}
+
+impl ExportError {
+    pub fn to_pretty(&self) -> String {
+        match self {
+            ExportError::Io(path, err) => {
+                format!("{}: {} ({})", "IO error".red(), err, path.display())
+            },
+            ExportError::InvalidManifest(path, err) => {
+                format!("{}: {} ({})", "Invalid manifest".red(), err, path.display())
+            },
+            ExportError::ModifiedFiles(files) => {
+                format!("{}: {} (use --force to back them up and overwrite)", "Generated files were modified by hand".red(), files.join(", "))
+            },
+            ExportError::ForeignFiles(files) => {
+                format!("{}: {} (use --force to back them up and overwrite)", "Files that were not generated would be overwritten".red(), files.join(", "))
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_pretty())
+    }
+}
+
+/// Summary of what was changed in the output directory during export
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    pub written: Vec<String>,
+    /// Stale files from the previous export that were removed
+    pub removed: Vec<String>,
+    /// Modified or not generated files that were copied to `<file>.bak` before being overwritten or removed
+    pub backed_up: Vec<String>,
+}
 
 /// Represents final program with many files
 pub struct Program {
     pub files: Vec<Arc<Mutex<ProgramFile>>>,
+    /// Handlers that are listed in the generated registry module
+    pub registry: Vec<RegistryEntry>,
+}
+
+/// Kind of handler listed in the registry module.
+/// Projects have only commands for now, events and components get their own kinds once they can be compiled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum RegistryEntryKind {
+    Command,
+}
+
+/// Handler listed in the registry module
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RegistryEntry {
+    pub kind: RegistryEntryKind,
+    pub name: String,
+    pub description: String,
+    /// Path of the file containing the handler, relative to the output directory
+    pub path: String,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Program {
     pub fn new() -> Self {
         Self {
             files: Vec::new(),
+            registry: Vec::new(),
         }
     }
 
+    /// Adds handler to the registry module
+    pub fn register(&mut self, entry: RegistryEntry) {
+        self.registry.push(entry);
+    }
+
+    /// Appends files and registry entries of another program
+    pub fn extend(&mut self, other: Program) {
+        self.files.extend(other.files);
+        self.registry.extend(other.registry);
+    }
+
+    /// Adds file with already generated code
+    pub fn add_file(&mut self, path: String, code: String) {
+        self.files.push(Arc::new(Mutex::new(ProgramFile { code, path })));
+    }
+
     pub fn debug_print(&self) {
         for file in self.files.iter() {
             println!("====< File: {} >=====", file.lock().unwrap().path);
@@ -22,7 +153,7 @@ impl Program {
         }
     }
 
-    pub fn get_file_builder(&mut self, path: String, var_cache: &Arc<Mutex<HashMap<PortIdentifier, String>>>, compiler: NodesJSCompiler) -> CodeBuilder {
+    pub fn get_file_builder(&mut self, path: String, var_cache: &Arc<Mutex<VarScope>>, compiler: NodesJSCompiler) -> CodeBuilder {
         let file = Arc::new(Mutex::new(ProgramFile {
             code: String::new(),
             path: path.clone(),
@@ -34,22 +165,95 @@ impl Program {
             lines: Arc::new(Mutex::new(Vec::new())),
             file,
             current_ident: Arc::new(Mutex::new(0)),
-            var_cache: Arc::clone(&var_cache),
+            var_cache: Arc::clone(var_cache),
             current_node_id: String::new(),
             compiler,
-            var_cache_stack: Vec::new(),
+            header: Arc::new(Mutex::new(Vec::new())),
+            imports: Arc::new(Mutex::new(BTreeMap::new())),
         }
     }
 
-    /// Export program files to given path
-    pub fn export_to_path(&self, path: PathBuf) {
+    /// Export program files to given path.
+    /// Files that were generated by the previous export but are no longer part of the program
+    /// are removed, all other files in the directory are left untouched.
+    /// If any file from the previous export that would be overwritten or removed was modified by hand,
+    /// or a file that is not in the manifest and was not generated would be overwritten,
+    /// nothing is exported, unless `force` is set, in which case these files are backed up first.
+    /// Files of versions before manifests start with the generated header, so they are overwritten as they are
+    pub fn export_to_path(&self, path: PathBuf, force: bool) -> Result<ExportReport, ExportError> {
+        let old_manifest = ProgramManifest::read(&path)?;
+        let mut report = ExportReport::default();
+
+        let new_files = self.files.iter()
+            .map(|file| file.lock().unwrap().path.clone())
+            .collect::<Vec<String>>();
+        // Manifest could have been edited by hand, never touch anything outside of the output directory
+        let stale_files = old_manifest.files.iter()
+            .filter(|f| !new_files.contains(f) && is_relative_path(f))
+            .cloned()
+            .collect::<Vec<String>>();
+
+        // Find files that would be lost before touching anything, only files of the previous export can be verified
+        let mut modified = Vec::new();
+        let mut foreign = Vec::new();
+        for file in new_files.iter().chain(stale_files.iter()) {
+            let file_path = path.join(file);
+            if !file_path.is_file() {
+                continue;
+            }
+
+            let contents = std::fs::read_to_string(&file_path).map_err(|e| ExportError::Io(file_path.clone(), e))?;
+            if !old_manifest.files.contains(file) {
+                if !is_generated(&contents) {
+                    foreign.push(file.clone());
+                }
+            } else if is_modified(&contents) {
+                modified.push(file.clone());
+            }
+        }
+
+        if !modified.is_empty() || !foreign.is_empty() {
+            if !force {
+                return Err(match modified.is_empty() {
+                    true => ExportError::ForeignFiles(foreign),
+                    false => ExportError::ModifiedFiles(modified),
+                });
+            }
+
+            for file in modified.into_iter().chain(foreign) {
+                let file_path = path.join(&file);
+                let backup_path = path.join(format!("{}.bak", file));
+                std::fs::copy(&file_path, &backup_path).map_err(|e| ExportError::Io(backup_path, e))?;
+                report.backed_up.push(file);
+            }
+        }
+
         for file in self.files.iter() {
-            let mut file_path = path.clone();
-            file_path.push(&file.lock().unwrap().path);
+            let file = file.lock().unwrap();
+            let file_path = path.join(&file.path);
             // Ensure that directory exists
-            std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
-            std::fs::write(file_path, &file.lock().unwrap().code).unwrap();
+            let parent = file_path.parent().unwrap();
+            std::fs::create_dir_all(parent).map_err(|e| ExportError::Io(parent.to_path_buf(), e))?;
+            std::fs::write(&file_path, file.contents()).map_err(|e| ExportError::Io(file_path.clone(), e))?;
+            report.written.push(file.path.clone());
+        }
+
+        // Remove stale files
+        for stale in stale_files {
+            let file_path = path.join(&stale);
+            if file_path.is_file() {
+                std::fs::remove_file(&file_path).map_err(|e| ExportError::Io(file_path.clone(), e))?;
+                report.removed.push(stale);
+            }
+            remove_empty_parents(&path, &file_path);
         }
+
+        ProgramManifest {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            files: report.written.clone(),
+        }.write(&path)?;
+
+        Ok(report)
     }
 
     /// Export program to string
@@ -60,7 +264,7 @@ impl Program {
             code.push_str("====< File: ");
             code.push_str(&file.lock().unwrap().path);
             code.push_str(" >=====\n");
-            code.push_str(&file.lock().unwrap().code);
+            code.push_str(&file.lock().unwrap().contents());
             code.push('\n');
             code.push('\n');
         }
@@ -69,28 +273,101 @@ impl Program {
     }
 }
 
+/// Checks if the path is relative and stays inside of the directory it is relative to
+fn is_relative_path(path: &str) -> bool {
+    Path::new(path).components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Removes empty directories left after removing a file, stopping at the root directory
+fn remove_empty_parents(root: &Path, file_path: &Path) {
+    let mut dir = file_path.parent();
+    while let Some(current) = dir {
+        if current == root || std::fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+/// Checks if the file starts with the header of generated files, all versions of the generator wrote it
+fn is_generated(contents: &str) -> bool {
+    contents.starts_with(GENERATED_HEADER)
+}
+
+/// Checks if contents of a generated file no longer match the hash from its header.
+/// Files without the header were generated by versions that didn't write it, so they can't be verified
+fn is_modified(contents: &str) -> bool {
+    let mut parts = contents.splitn(3, '\n');
+    let header = parts.next().unwrap_or_default().trim_end_matches('\r');
+    if !header.starts_with(GENERATED_HEADER) {
+        return false;
+    }
+
+    let hash_line = parts.next().unwrap_or_default().trim_end_matches('\r');
+    match hash_line.strip_prefix(HASH_HEADER) {
+        Some(hash) => hash != hash_code(parts.next().unwrap_or_default()),
+        // Files generated before hashes were recorded can't be verified
+        None => false,
+    }
+}
+
+/// Makes part of a variable name from a node type or port key. Characters that can't be
+/// in a TypeScript identifier, like `/` in ids of package nodes, are replaced with `_`,
+/// names stay unique because they end with a number from the compiler
+fn identifier_part(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// Hashes generated code, line endings are normalized so that checkouts with CRLF still match
+fn hash_code(code: &str) -> String {
+    let code = code.replace("\r\n", "\n");
+    format!("{:x}", Sha256::digest(code.as_bytes()))
+}
+
 pub struct ProgramFile {
     pub code: String,
     pub path: String,
 }
 
+impl ProgramFile {
+    /// Returns code of the file preceded by a header with generator version and hash of the code
+    pub fn contents(&self) -> String {
+        format!("{} v{}\n{}{}\n{}", GENERATED_HEADER, env!("CARGO_PKG_VERSION"), HASH_HEADER, hash_code(&self.code), self.code)
+    }
+}
+
 #[derive(Clone)]
 pub struct CodeBuilder {
     pub lines: Arc<Mutex<Vec<String>>>,
     pub file: Arc<Mutex<ProgramFile>>,
     pub current_ident: Arc<Mutex<usize>>,
-    pub var_cache: Arc<Mutex<HashMap<PortIdentifier, String>>>,
+    pub var_cache: Arc<Mutex<VarScope>>,
     pub current_node_id: String,
     pub compiler: NodesJSCompiler,
-    var_cache_stack: Vec<HashMap<PortIdentifier, String>>,
+    /// Lines placed above the imports
+    header: Arc<Mutex<Vec<String>>>,
+    /// Imported names grouped by module, shared between all builders of a file
+    imports: Arc<Mutex<BTreeMap<String, BTreeSet<String>>>>,
 }
 
 impl CodeBuilder {
-    pub fn finalize(self) -> () {
+    pub fn finalize(self) {
         let mut code = String::new();
 
+        for line in self.header.lock().unwrap().iter() {
+            code.push_str(line);
+            code.push('\n');
+        }
+
+        for (path, imports) in self.imports.lock().unwrap().iter() {
+            let imports = imports.iter().cloned().collect::<Vec<String>>().join(", ");
+            code.push_str(&format!("import {{ {} }} from \"{}\";\n", imports, path));
+        }
+
         for line in self.lines.lock().unwrap().iter() {
-            code.push_str(&line);
+            code.push_str(line);
             code.push('\n');
         }
 
@@ -117,14 +394,15 @@ impl CodeBuilder {
             var_cache: Arc::clone(&self.var_cache),
             current_node_id: self.current_node_id.clone(),
             compiler: self.compiler.clone(),
-            var_cache_stack: Vec::new(),
+            header: Arc::clone(&self.header),
+            imports: Arc::clone(&self.imports),
         }
     }
 
     pub fn add_line(&mut self, line: String) {
         let mut ident = String::new();
 
-        for _ in 0..self.current_ident.lock().unwrap().clone() {
+        for _ in 0..*self.current_ident.lock().unwrap() {
             ident.push_str("    ");
         }
 
@@ -147,14 +425,18 @@ impl CodeBuilder {
         self.pop_stack();
     }
 
+    /// Imports comma separated names from the given module.
+    /// Imports are deduplicated and placed on top of the file when it is finalized
     pub fn add_import(&mut self, imports: String, path: String) {
-        self.lines.lock().unwrap().insert(0, 
-            format!("import {{{}}} from \"{}\"", imports, path)
-        );
+        let mut all_imports = self.imports.lock().unwrap();
+        let module_imports = all_imports.entry(path).or_default();
+        for import in imports.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            module_imports.insert(import.to_string());
+        }
     }
 
     pub fn add_on_top(&mut self, line: String) {
-        self.lines.lock().unwrap().insert(0, line);
+        self.header.lock().unwrap().insert(0, line);
     }
 
     pub fn get_in_var(&mut self, port_name: String) -> String {
@@ -166,30 +448,126 @@ impl CodeBuilder {
         }
 
         if let Some(var) = var_cache.get(&port) {
-            return var.clone();
+            var.clone()
         } else {
-            return "undefined".to_string();
+            "undefined".to_string()
         }
     }
-    
+
+    /// Returns variables of all instances of the variadic input, in the order they are in the editor
+    pub fn get_inputs(&mut self, key: String) -> rhai::Array {
+        let keys = self.compiler.current_flow.as_ref()
+            .and_then(|flow| flow.get_node(&self.current_node_id).ok())
+            .map(|node| node.variadic_keys(&key))
+            .unwrap_or_default();
+
+        keys.into_iter()
+            .map(|key| Dynamic::from(self.get_in_var(key)))
+            .collect()
+    }
+
+    /// Returns TypeScript type of the value connected to the given input.
+    /// If the input is not connected, type of the hardcoded value is used.
+    /// Returns `any` if the type is not known
+    pub fn get_input_type(&mut self, port_key: String) -> String {
+        match self.get_input_ts_type(&self.current_node_id, &port_key) {
+            Some(ts_type) => {
+                if let Some(import) = &ts_type.import {
+                    self.add_import(ts_type.name.clone(), import.clone());
+                }
+                ts_type.name
+            },
+            None => "any".to_string(),
+        }
+    }
+
+    /// Returns TypeScript type of the value connected to the input of the node, or of its hardcoded value
+    fn get_input_ts_type(&self, node_uid: &str, port_key: &str) -> Option<TsType> {
+        let port = PortIdentifier::Input { node_uid: node_uid.to_string(), port_key: port_key.to_string() };
+        let flow = self.compiler.current_flow.as_ref()?;
+
+        match flow.get_target(&port) {
+            Some(target) => self.get_port_ts_type(&target),
+            None => flow.get_node(node_uid).ok()
+                .and_then(|node| node.get_hardcoded(port_key))
+                .and_then(TsType::from_value),
+        }
+    }
+
+    /// Returns TypeScript type of the given output port.
+    /// Type set during compilation takes precedence over the one from node declaration
+    fn get_port_ts_type(&self, port: &PortIdentifier) -> Option<TsType> {
+        let (node_uid, port_key) = match port {
+            PortIdentifier::Output { node_uid, port_key } => (node_uid, port_key),
+            _ => return None,
+        };
+
+        let comp_time = |data_key: &str| PortIdentifier::CompTime { node_uid: node_uid.clone(), port_key: port_key.clone(), data_key: data_key.to_string() };
+        {
+            let var_cache = self.var_cache.lock().unwrap();
+            if let Some(name) = var_cache.get(&comp_time(TS_TYPE_DATA_KEY)) {
+                return Some(TsType {
+                    name: name.clone(),
+                    import: var_cache.get(&comp_time(TS_IMPORT_DATA_KEY)).cloned(),
+                });
+            }
+        }
+
+        let flow_node = self.compiler.current_flow.as_ref()?.get_node(node_uid).ok()?;
+        let node = self.compiler.registry().get(&flow_node.node_type)?;
+        let output = &node.metadata.outputs.values.get(port_key)?.ty;
+
+        // Generic output has the type of the first value of an input with the same type variable
+        if let (Some(generic), None) = (&output.generic, &output.ts_type) {
+            return node.metadata.inputs.keys.iter()
+                .filter_map(|key| Some((key, node.metadata.inputs.values.get(key)?)))
+                .filter(|(_, input)| input.ty.generic.as_ref() == Some(generic))
+                .flat_map(|(key, input)| match input.variadic {
+                    true => flow_node.variadic_keys(key),
+                    false => vec![key.clone()],
+                })
+                .find_map(|key| self.get_input_ts_type(node_uid, &key));
+        }
+        TsType::from_port(output)
+    }
+
+    /// Sets TypeScript type of an output port, used for outputs
+    /// whose type is known only during compilation
+    pub(crate) fn set_port_ts_type(&mut self, port: PortIdentifier, ts_type: TsType) {
+        let (node_uid, port_key) = match port {
+            PortIdentifier::Output { node_uid, port_key } => (node_uid, port_key),
+            _ => return,
+        };
+
+        let mut var_cache = self.var_cache.lock().unwrap();
+        var_cache.insert(
+            PortIdentifier::CompTime { node_uid: node_uid.clone(), port_key: port_key.clone(), data_key: TS_TYPE_DATA_KEY.to_string() },
+            ts_type.name
+        );
+        if let Some(import) = ts_type.import {
+            var_cache.insert(
+                PortIdentifier::CompTime { node_uid, port_key, data_key: TS_IMPORT_DATA_KEY.to_string() },
+                import
+            );
+        }
+    }
+
     pub fn get_comp_time_data(&mut self, port_key: String, data_key: String) -> String {
         let var_cache = self.var_cache.lock().unwrap();
         // Get whatever this port is connected to
         let port = PortIdentifier::Input { node_uid: self.current_node_id.clone(), port_key: port_key.clone() };
-        let port = self.compiler.get_flow_target(self.compiler.current_flow.as_ref().unwrap(), port).unwrap();
-
-        // Convert port to comp time data
-        let port = match port {
-            PortIdentifier::Output { node_uid, port_key } => {
+        let port = match self.compiler.current_flow.as_ref().and_then(|flow| flow.get_target(&port)) {
+            Some(PortIdentifier::Output { node_uid, port_key }) => {
+                // Convert port to comp time data
                 PortIdentifier::CompTime { node_uid, port_key, data_key }
             },
-            _ => unreachable!()
+            _ => return "undefined".to_string()
         };
 
         if let Some(var) = var_cache.get(&port) {
-            return var.clone();
+            var.clone()
         } else {
-            return "undefined".to_string();
+            "undefined".to_string()
         }
     }
 
@@ -202,20 +580,35 @@ impl CodeBuilder {
     pub fn get_out_var(&mut self, port_key: String) -> String {
         let mut var_cache = self.var_cache.lock().unwrap();
         let port = PortIdentifier::Output { node_uid: self.current_node_id.clone(), port_key: port_key.clone() };
-        let nt = self.compiler.current_flow.as_ref().unwrap().get_node(&self.current_node_id).node_type.clone();
+        let nt = self.compiler.current_flow.as_ref()
+            .and_then(|flow| flow.get_node(&self.current_node_id).ok())
+            .map(|node| node.node_type.clone())
+            .unwrap_or_default();
 
         if let Some(var) = var_cache.get(&port) {
-            return var.clone();
+            var.clone()
         } else {
-            let vn = format!("__io_N{}_O{}_{}", nt, port_key, NodesJSCompiler::random_var_name());
+            let vn = format!("__io_N{}_O{}_{}", identifier_part(&nt), identifier_part(&port_key), self.compiler.next_var_name());
             var_cache.insert(port.clone(), vn.clone());
-            return vn;
+            vn
         }
     }
 
+    /// Declares output variable with the given value.
+    /// Variable is annotated with the output type if it is known, otherwise it is inferred
     pub fn set_output(&mut self, port: String, value: String) {
         let ovar = self.get_out_var(port.clone());
-        self.add_line(format!("const {} = {}", ovar, value));
+        let ts_type = self.get_port_ts_type(&PortIdentifier::Output { node_uid: self.current_node_id.clone(), port_key: port });
+
+        match ts_type {
+            Some(ts_type) => {
+                if let Some(import) = &ts_type.import {
+                    self.add_import(ts_type.name.clone(), import.clone());
+                }
+                self.add_line(format!("const {}: {} = {};", ovar, ts_type.name, value));
+            },
+            None => self.add_line(format!("const {} = {};", ovar, value)),
+        }
     }
 
     pub fn bind_io(&mut self, ip: String, op: String) {
@@ -232,32 +625,30 @@ impl CodeBuilder {
     }
 
     pub fn push_stack(&mut self) {
-        self.var_cache_stack.push(self.var_cache.lock().unwrap().clone());
+        self.var_cache.lock().unwrap().push();
     }
 
     pub fn pop_stack(&mut self) {
-        let mut var_cache = self.var_cache.lock().unwrap();
-        let new_cache = self.var_cache_stack.pop().unwrap();
-
-        var_cache.clear();
-        var_cache.extend(new_cache);
+        self.var_cache.lock().unwrap().pop();
     }
 
-    pub fn compile_flow_output_here(&mut self, flow_port: String) {
+    pub fn compile_flow_output_here(&mut self, flow_port: String) -> Result<(), Box<EvalAltResult>> {
         // Compile the flow output here
         let new_builder = self.clone_empty();
-        let cf = self.compiler.current_flow.as_ref().unwrap().clone();
+        let cf = Arc::clone(self.compiler.current_flow.as_ref().ok_or("Cannot compile flow output outside of a flow")?);
         let port = PortIdentifier::Output { node_uid: self.current_node_id.clone(), port_key: flow_port };
+        let node = cf.get_node(&self.current_node_id).map_err(|err| err.to_pretty())?;
 
-        self.compiler.compile_flow_from_port(&cf, new_builder.clone(), port, cf.get_node(&self.current_node_id))
-            .expect(&format!("Failed to compile flow output inside node: {}", self.current_node_id));
+        self.compiler.compile_flow_from_port(&cf, new_builder.clone(), port, node)
+            .map_err(|err| format!("Failed to compile flow output inside node {}: {}", self.current_node_id, err.to_pretty()))?;
 
         // Add the code to the current builder
         self.add_lines(new_builder.finalize_vec());
+        Ok(())
     }
 
     pub fn get_random_var_name(&mut self) -> String {
-        NodesJSCompiler::random_var_name()
+        self.compiler.next_var_name()
     }
 }
 
@@ -271,6 +662,8 @@ impl CustomType for CodeBuilder {
             .with_fn("end_block", Self::end_block)
             .with_fn("add_import", Self::add_import)
             .with_fn("get_input", Self::get_in_var)
+            .with_fn("get_inputs", Self::get_inputs)
+            .with_fn("get_input_type", Self::get_input_type)
             .with_fn("get_out_var", Self::get_out_var)
             .with_fn("set_output", Self::set_output)
             .with_fn("bind_io", Self::bind_io)
@@ -282,4 +675,122 @@ impl CustomType for CodeBuilder {
             .with_fn("get_random_var_name", Self::get_random_var_name)
             .with_fn("compile_flow_output_here", Self::compile_flow_output_here);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_builder(program: &mut Program) -> CodeBuilder {
+        let project = serde_json::from_value(serde_json::json!({
+            "metadata": { "name": "test", "formatVersion": 2 },
+            "content": { "commands": [] },
+        })).unwrap();
+        let compiler = NodesJSCompiler::new(project);
+        program.get_file_builder("test.ts".to_string(), &Arc::clone(&compiler.var_cache), compiler)
+    }
+
+    fn program(files: &[(&str, &str)]) -> Program {
+        let mut program = Program::new();
+        for (path, code) in files {
+            program.add_file(path.to_string(), code.to_string());
+        }
+        program
+    }
+
+    #[test]
+    fn hash_ignores_line_endings() {
+        assert_eq!(hash_code("a\nb\n"), hash_code("a\r\nb\r\n"));
+        assert_ne!(hash_code("a\nb\n"), hash_code("a\nc\n"));
+    }
+
+    #[test]
+    fn modified_files() {
+        let file = ProgramFile { code: "run();\n".to_string(), path: "a.ts".to_string() };
+        let contents = file.contents();
+        assert!(!is_modified(&contents));
+        assert!(!is_modified(&contents.replace('\n', "\r\n")));
+        assert!(is_modified(&contents.replace("run", "stop")));
+        // Output of versions without the header or without the hash
+        assert!(!is_modified("run();\n"));
+        assert!(!is_modified(&format!("{} v0.1.0\nrun();\n", GENERATED_HEADER)));
+    }
+
+    #[test]
+    fn export_refuses_modified_files() {
+        let dir = tempfile::tempdir().unwrap();
+        program(&[("a.ts", "run();\n")]).export_to_path(dir.path().to_path_buf(), false).unwrap();
+        let edited = std::fs::read_to_string(dir.path().join("a.ts")).unwrap().replace("run", "stop");
+        std::fs::write(dir.path().join("a.ts"), &edited).unwrap();
+
+        let second = program(&[("a.ts", "walk();\n")]);
+        assert!(matches!(second.export_to_path(dir.path().to_path_buf(), false), Err(ExportError::ModifiedFiles(files)) if files == ["a.ts"]));
+
+        let report = second.export_to_path(dir.path().to_path_buf(), true).unwrap();
+        assert_eq!(report.backed_up, ["a.ts"]);
+        assert_eq!(std::fs::read_to_string(dir.path().join("a.ts.bak")).unwrap(), edited);
+    }
+
+    #[test]
+    fn export_overwrites_legacy_output() {
+        // Output of versions before manifests and hashes, only the header was written
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("commands")).unwrap();
+        std::fs::write(dir.path().join("commands/hello.ts"), format!("{}\n// @ts-nocheck\nold command\n", GENERATED_HEADER)).unwrap();
+
+        let files = [("index.ts", "index();\n"), ("commands/hello.ts", "hello();\n")];
+        let report = program(&files).export_to_path(dir.path().to_path_buf(), false).unwrap();
+        assert!(report.backed_up.is_empty());
+        assert!(std::fs::read_to_string(dir.path().join("commands/hello.ts")).unwrap().ends_with("hello();\n"));
+
+        // Manifest without headers in the files, written by the version that added manifests
+        ProgramManifest { version: "0.1.0".to_string(), files: vec!["index.ts".to_string()] }.write(dir.path()).unwrap();
+        std::fs::write(dir.path().join("index.ts"), "old index\n").unwrap();
+        let report = program(&files).export_to_path(dir.path().to_path_buf(), false).unwrap();
+        assert!(report.backed_up.is_empty());
+        assert_eq!(report.written, ["index.ts", "commands/hello.ts"]);
+    }
+
+    #[test]
+    fn export_refuses_files_that_were_not_generated() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.ts"), "main();\n").unwrap();
+
+        let files = [("index.ts", "index();\n")];
+        assert!(matches!(program(&files).export_to_path(dir.path().to_path_buf(), false), Err(ExportError::ForeignFiles(files)) if files == ["index.ts"]));
+        assert_eq!(std::fs::read_to_string(dir.path().join("index.ts")).unwrap(), "main();\n");
+
+        // Manifest of another export doesn't own the file either
+        program(&[("other.ts", "other();\n")]).export_to_path(dir.path().to_path_buf(), false).unwrap();
+        assert!(matches!(program(&files).export_to_path(dir.path().to_path_buf(), false), Err(ExportError::ForeignFiles(_))));
+
+        let report = program(&files).export_to_path(dir.path().to_path_buf(), true).unwrap();
+        assert_eq!(report.backed_up, ["index.ts"]);
+        assert_eq!(std::fs::read_to_string(dir.path().join("index.ts.bak")).unwrap(), "main();\n");
+        assert!(std::fs::read_to_string(dir.path().join("index.ts")).unwrap().ends_with("index();\n"));
+    }
+
+    #[test]
+    fn identifier_parts() {
+        assert_eq!(identifier_part("get_option"), "get_option");
+        assert_eq!(identifier_part("my-pkg/greet"), "my_pkg_greet");
+        assert_eq!(identifier_part("values[2]"), "values_2_");
+    }
+
+    #[test]
+    fn imports_are_deduplicated_and_sorted() {
+        let mut program = Program::new();
+        let mut builder = file_builder(&mut program);
+        builder.add_import("User, GuildMember".to_string(), "discord.js".to_string());
+        builder.clone_empty().add_import("User,".to_string(), "discord.js".to_string());
+        builder.add_import("format".to_string(), "./utils".to_string());
+        builder.add_on_top("// header".to_string());
+        builder.add_line("run();".to_string());
+        builder.finalize();
+
+        assert_eq!(
+            program.files[0].lock().unwrap().code,
+            "// header\nimport { format } from \"./utils\";\nimport { GuildMember, User } from \"discord.js\";\nrun();\n"
+        );
+    }
+}
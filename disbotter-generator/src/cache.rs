@@ -0,0 +1,180 @@
+use std::{collections::{HashMap, VecDeque, HashSet}, path::PathBuf, sync::Mutex};
+
+use sha2::{Sha256, Digest};
+
+use crate::builder::RegistryEntry;
+
+/// Compiled output of a single command, this is what gets reused
+/// when the command didn't change since the last compilation
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct CachedCommand {
+    /// Generated files as (path, code) pairs
+    pub files: Vec<(String, String)>,
+    pub registry: Vec<RegistryEntry>,
+}
+
+/// Storage for compiled commands, keyed by hash of everything the output depends on
+pub trait CompileCache: Send + Sync {
+    fn get(&self, key: &str) -> Option<CachedCommand>;
+    fn put(&self, key: &str, command: CachedCommand);
+    /// Called after the whole project was compiled with keys of all commands in the project,
+    /// caches can use it to drop outputs of commands that no longer exist
+    fn retain(&self, _keys: &HashSet<String>) {}
+}
+
+/// Returns hex encoded sha256 hash of the data
+pub fn hash_bytes(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// In-memory cache, used by the server where it is shared between all requests.
+/// When it is full, the least recently used entries are dropped first
+pub struct MemoryCache {
+    capacity: usize,
+    entries: Mutex<(HashMap<String, CachedCommand>, VecDeque<String>)>,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new((HashMap::new(), VecDeque::new())),
+        }
+    }
+}
+
+impl CompileCache for MemoryCache {
+    fn get(&self, key: &str) -> Option<CachedCommand> {
+        let mut entries = self.entries.lock().unwrap();
+        let (map, order) = &mut *entries;
+        let command = map.get(key).cloned()?;
+
+        // Move the entry to the back, so the most used commands are the last ones to be dropped
+        if let Some(position) = order.iter().position(|k| k == key) {
+            order.remove(position);
+        }
+        order.push_back(key.to_string());
+        Some(command)
+    }
+
+    fn put(&self, key: &str, command: CachedCommand) {
+        let mut entries = self.entries.lock().unwrap();
+        let (map, order) = &mut *entries;
+
+        if map.insert(key.to_string(), command).is_none() {
+            order.push_back(key.to_string());
+        }
+
+        while map.len() > self.capacity {
+            match order.pop_front() {
+                Some(least_used) => { map.remove(&least_used); },
+                None => break,
+            }
+        }
+    }
+}
+
+/// Cache stored on disk, used by the CLI so that the outputs survive between runs.
+/// Every command is stored in its own `<key>.json` file
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+impl CompileCache for DiskCache {
+    fn get(&self, key: &str) -> Option<CachedCommand> {
+        let content = std::fs::read_to_string(self.entry_path(key)).ok()?;
+        // Broken entries are treated as missing, they will be overwritten after compilation
+        serde_json::from_str(&content).ok()
+    }
+
+    fn put(&self, key: &str, command: CachedCommand) {
+        // Cache is only an optimization, so failing to write it is not an error
+        if std::fs::create_dir_all(&self.dir).is_ok() {
+            std::fs::write(self.entry_path(key), serde_json::to_string(&command).unwrap()).ok();
+        }
+    }
+
+    fn retain(&self, keys: &HashSet<String>) {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_used = path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(|stem| keys.contains(stem))
+                .unwrap_or(false);
+
+            if path.extension().is_some_and(|ext| ext == "json") && !is_used {
+                std::fs::remove_file(path).ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(code: &str) -> CachedCommand {
+        CachedCommand {
+            files: vec![("commands/test.ts".to_string(), code.to_string())],
+            registry: vec![],
+        }
+    }
+
+    #[test]
+    fn memory_cache_drops_least_recently_used() {
+        let cache = MemoryCache::new(2);
+        cache.put("a", command("a"));
+        cache.put("b", command("b"));
+        assert!(cache.get("a").is_some());
+        cache.put("c", command("c"));
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn memory_cache_replaces_entries() {
+        let cache = MemoryCache::new(1);
+        cache.put("a", command("old"));
+        cache.put("a", command("new"));
+        assert_eq!(cache.get("a").unwrap().files[0].1, "new");
+    }
+
+    #[test]
+    fn disk_cache_retains_used_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path().join("cache"));
+        cache.put("a", command("a"));
+        cache.put("b", command("b"));
+        std::fs::write(dir.path().join("cache/notes.txt"), "kept").unwrap();
+
+        cache.retain(&HashSet::from(["a".to_string()]));
+        assert_eq!(cache.get("a").unwrap().files[0].1, "a");
+        assert!(cache.get("b").is_none());
+        assert!(dir.path().join("cache/notes.txt").exists());
+    }
+
+    #[test]
+    fn disk_cache_ignores_broken_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path().to_path_buf());
+        std::fs::write(dir.path().join("a.json"), "{").unwrap();
+        assert!(cache.get("a").is_none());
+    }
+}
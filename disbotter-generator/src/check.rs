@@ -0,0 +1,741 @@
+use std::collections::HashMap;
+
+use colored::*;
+use rayon::prelude::*;
+
+use crate::{compiler::NodesJSCompiler, loader::{parse_variadic_key, DataType, InputConstraints, NodeIOTy}, project::{DisbotterProjectCommand, DisbotterFlowConnection, DisbotterFlowNode, START_NODE}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Problem found while checking a project
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Name of the command the problem is in
+    pub command: String,
+    /// Uid of the node the problem is in, if it is related to a single node
+    pub node: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(command: &DisbotterProjectCommand, node: Option<&str>, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            command: command.name.clone(),
+            node: node.map(str::to_string),
+            message,
+        }
+    }
+
+    fn warning(command: &DisbotterProjectCommand, node: Option<&str>, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(command, node, message)
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn to_pretty(&self) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error".red(),
+            Severity::Warning => "warning".yellow(),
+        };
+        let location = match &self.node {
+            Some(node) => format!("{} > {}", self.command, node),
+            None => self.command.clone(),
+        };
+        format!("{} [{}]: {}", severity, location.cyan(), self.message)
+    }
+}
+
+/// Checks the whole project without writing any output.
+/// Flows of all commands are validated and type checked, commands without errors are then
+/// compiled into a discarded program, so errors from node actions are reported too
+pub fn check_project(compiler: &NodesJSCompiler) -> Vec<Diagnostic> {
+    compiler.project().content.commands.par_iter()
+        .flat_map_iter(|command| {
+            let mut diagnostics = check_flow(compiler, command);
+
+            if !diagnostics.iter().any(Diagnostic::is_error) {
+                if let Err(err) = compiler.dry_run_command(command) {
+                    diagnostics.push(Diagnostic::error(command, None, err.to_pretty()));
+                }
+            }
+
+            diagnostics
+        })
+        .collect()
+}
+
+/// Validates and type checks flows of all commands without compiling them,
+/// used before compiling the project, which reports errors from node actions itself
+pub fn check_project_flows(compiler: &NodesJSCompiler) -> Vec<Diagnostic> {
+    compiler.project().content.commands.par_iter()
+        .flat_map_iter(|command| check_flow(compiler, command))
+        .collect()
+}
+
+fn check_deprecated_nodes(compiler: &NodesJSCompiler, command: &DisbotterProjectCommand) -> Vec<Diagnostic> {
+    command.flow.nodes.iter()
+        .filter_map(|node| {
+            let metadata = &compiler.registry().get(&node.node_type)?.metadata;
+            let deprecation = metadata.deprecated.as_ref()?;
+            let message = match &deprecation.replacement {
+                Some(replacement) => format!("Node \"{}\" ({}) is deprecated, use \"{}\" instead", metadata.title, metadata.id, replacement),
+                None => format!("Node \"{}\" ({}) is deprecated", metadata.title, metadata.id),
+            };
+            Some(Diagnostic::warning(command, Some(&node.uid), message))
+        })
+        .collect()
+}
+
+/// Validates structure of the command flow and types of all connections
+fn check_flow(compiler: &NodesJSCompiler, command: &DisbotterProjectCommand) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let flow = &command.flow;
+
+    // Nodes
+    let mut nodes = HashMap::<&str, &DisbotterFlowNode>::new();
+    for node in flow.nodes.iter() {
+        if nodes.insert(&node.uid, node).is_some() {
+            diagnostics.push(Diagnostic::error(command, Some(&node.uid), "Multiple nodes have this uid".to_string()));
+        }
+    }
+
+    let mut ports = HashMap::<&str, NodePorts>::new();
+    for node in flow.nodes.iter() {
+        match NodePorts::of(compiler, command, &node.node_type) {
+            Some(node_ports) => { ports.insert(&node.uid, node_ports); },
+            None => diagnostics.push(Diagnostic::error(command, Some(&node.uid), format!("Unknown node type \"{}\"", node.node_type))),
+        }
+    }
+
+    diagnostics.extend(check_deprecated_nodes(compiler, command));
+    let bindings = bind_type_variables(command, &ports, &mut diagnostics);
+
+    let start_nodes = flow.nodes.iter().filter(|n| n.node_type == START_NODE).count();
+    if start_nodes == 0 {
+        diagnostics.push(Diagnostic::error(command, None, "Command has no start node".to_string()));
+    } else if start_nodes > 1 {
+        diagnostics.push(Diagnostic::warning(command, None, "Command has multiple start nodes, only the first one is compiled".to_string()));
+    }
+
+    // Connections
+    let mut connected_inputs = HashMap::<(&str, &str), usize>::new();
+    let mut connected_flow_outputs = HashMap::<(&str, &str), usize>::new();
+    for conn in flow.connections.iter() {
+        let mut endpoints_exist = true;
+        for uid in [&conn.from, &conn.to] {
+            if !nodes.contains_key(uid.as_str()) {
+                diagnostics.push(Diagnostic::error(command, None, format!("Connection references node \"{}\" that doesn't exist", uid)));
+                endpoints_exist = false;
+            }
+        }
+        if !endpoints_exist {
+            continue;
+        }
+
+        *connected_inputs.entry((&conn.to, &conn.to_key)).or_default() += 1;
+
+        // Types can be checked only if both node types are known
+        let (from_ports, to_ports) = match (ports.get(conn.from.as_str()), ports.get(conn.to.as_str())) {
+            (Some(from_ports), Some(to_ports)) => (from_ports, to_ports),
+            _ => continue,
+        };
+
+        let output = from_ports.outputs.get(&conn.from_key).map(|output| bound_type(&bindings, &conn.from, output));
+        let input = to_ports.input(nodes[conn.to.as_str()], &conn.to_key).map(|input| bound_type(&bindings, &conn.to, input));
+        if output.is_none() {
+            diagnostics.push(Diagnostic::error(command, Some(&conn.from), format!("Node has no output \"{}\"", conn.from_key)));
+        }
+        if input.is_none() {
+            diagnostics.push(Diagnostic::error(command, Some(&conn.to), format!("Node has no input \"{}\"", conn.to_key)));
+        }
+        let (output, input) = match (output, input) {
+            (Some(output), Some(input)) => (output, input),
+            _ => continue,
+        };
+
+        if output.ty == DataType::Flow {
+            *connected_flow_outputs.entry((&conn.from, &conn.from_key)).or_default() += 1;
+        }
+
+        if !is_compatible(&output, &input) {
+            let message = format!(
+                "Input \"{}\" of type {} can't be connected to output \"{}\" of node {} with type {}",
+                conn.to_key, describe_type(&input), conn.from_key, conn.from, describe_type(&output)
+            );
+            diagnostics.push(match input.generic.is_some() {
+                true => to_ports.type_mismatch(command, &conn.to, message),
+                false => Diagnostic::error(command, Some(&conn.to), message),
+            });
+        }
+    }
+
+    // Inputs without a value
+    for node in flow.nodes.iter() {
+        let node_ports = match ports.get(node.uid.as_str()) {
+            Some(node_ports) => node_ports,
+            None => continue,
+        };
+        for (key, instance) in node_ports.input_instances(node) {
+            let is_flow = node_ports.inputs[key].ty == DataType::Flow;
+            let connected = connected_inputs.contains_key(&(node.uid.as_str(), instance.as_str()));
+            if let Some(constraints) = node_ports.constraints.get(key) {
+                if let Err(message) = constraints.check_input(node.get_hardcoded(&instance), connected) {
+                    diagnostics.push(Diagnostic::error(command, Some(&node.uid), format!("Input \"{}\": {}", instance, message)));
+                    continue;
+                }
+            }
+            let has_value = node.get_hardcoded(&instance).is_some()
+                || node_ports.defaults.contains(key)
+                || connected;
+            if !is_flow && !has_value {
+                diagnostics.push(Diagnostic::warning(command, Some(&node.uid), format!("Input \"{}\" is not connected and has no value", instance)));
+            }
+        }
+    }
+
+    for ((uid, key), count) in sorted(connected_inputs) {
+        if count > 1 {
+            diagnostics.push(Diagnostic::warning(command, Some(uid), format!("Input \"{}\" has {} connections, only the first one is used", key, count)));
+        }
+    }
+    for ((uid, key), count) in sorted(connected_flow_outputs) {
+        if count > 1 {
+            diagnostics.push(Diagnostic::warning(command, Some(uid), format!("Flow output \"{}\" has {} connections, only the first one is followed", key, count)));
+        }
+    }
+
+    diagnostics
+}
+
+/// Types bound to the type variables of every node, by uid of the node and name of the variable
+type Bindings = HashMap<String, HashMap<String, NodeIOTy>>;
+
+/// Binds type variables of every node to the types of values that flow into its generic inputs,
+/// either from the connected outputs or from the hardcoded values. Generic outputs have the bound type,
+/// so types pass through chains of generic nodes. Connections are then checked against the bound types.
+/// A variable is bound by the first of its inputs in declaration order whose type is known,
+/// so the input that gets the error doesn't depend on the order the connections were made in.
+/// Nodes working with a user variable share their type variable with all nodes using the same variable
+fn bind_type_variables(
+    command: &DisbotterProjectCommand,
+    ports: &HashMap<&str, NodePorts>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Bindings {
+    let flow = &command.flow;
+    // Only the first connection of an input is used
+    let mut sources = HashMap::<(&str, &str), &DisbotterFlowConnection>::new();
+    for conn in flow.connections.iter() {
+        sources.entry((conn.to.as_str(), conn.to_key.as_str())).or_insert(conn);
+    }
+    let variable_name = |node: &DisbotterFlowNode, node_ports: &NodePorts| {
+        let input = node_ports.variable_input.as_ref()?;
+        if sources.contains_key(&(node.uid.as_str(), input.as_str())) {
+            return None;
+        }
+        node.get_hardcoded(input)?.as_str().filter(|name| !name.is_empty()).map(str::to_string)
+    };
+
+    let mut bindings = Bindings::new();
+    // Types of user variables by their name
+    let mut variables = HashMap::<String, NodeIOTy>::new();
+    // Types variable nodes got from their own inputs, they have to match the type of the variable
+    let mut variable_values = vec![];
+    // Inputs connected to generic outputs that aren't bound yet are waited for,
+    // until nothing else can be bound, then they are skipped
+    let mut skip_pending = false;
+    loop {
+        let mut decided = vec![];
+        for node in flow.nodes.iter() {
+            let node_ports = match ports.get(node.uid.as_str()) {
+                Some(node_ports) => node_ports,
+                None => continue,
+            };
+            for generic in node_ports.generics.iter() {
+                if bindings.get(&node.uid).is_some_and(|bound| bound.contains_key(generic)) {
+                    continue;
+                }
+                match input_candidate(node, node_ports, generic, &sources, ports, &bindings, skip_pending) {
+                    Candidate::Known(ty) => decided.push((node, generic, ty, false)),
+                    Candidate::Pending => {},
+                    Candidate::None => {
+                        if let Some(ty) = variable_name(node, node_ports).and_then(|name| variables.get(&name)) {
+                            decided.push((node, generic, ty.clone(), true));
+                        }
+                    }
+                }
+            }
+        }
+
+        if decided.is_empty() {
+            if skip_pending {
+                break;
+            }
+            skip_pending = true;
+            continue;
+        }
+        skip_pending = false;
+
+        // All bindings of an iteration are decided from the same state, so the order of the nodes doesn't matter
+        // except for the variables, which get the type of the first node in the flow that uses them
+        for (node, generic, ty, from_variable) in decided {
+            if !from_variable {
+                if let Some(name) = variable_name(node, &ports[node.uid.as_str()]) {
+                    variables.entry(name.clone()).or_insert_with(|| ty.clone());
+                    variable_values.push((node, name, ty.clone()));
+                }
+            }
+            bindings.entry(node.uid.clone()).or_default().insert(generic.clone(), ty);
+        }
+    }
+
+    // Hardcoded values that don't match the bound type, connected ones are reported with the connections
+    for node in flow.nodes.iter() {
+        let node_ports = match ports.get(node.uid.as_str()) {
+            Some(node_ports) => node_ports,
+            None => continue,
+        };
+        for (key, instance) in node_ports.input_instances(node) {
+            let generic = match &node_ports.inputs[key].generic {
+                Some(generic) => generic,
+                None => continue,
+            };
+            if sources.contains_key(&(node.uid.as_str(), instance.as_str())) {
+                continue;
+            }
+            let (ty, bound) = match (node.get_hardcoded(&instance).and_then(value_type), bindings.get(&node.uid).and_then(|bound| bound.get(generic))) {
+                (Some(ty), Some(bound)) => (ty, bound),
+                _ => continue,
+            };
+            if bound.ty != ty {
+                let message = format!("Input \"{}\" has a {} value, but its type {} is {}", instance, ty.name(), generic, describe_type(bound));
+                diagnostics.push(node_ports.type_mismatch(command, &node.uid, message));
+            }
+        }
+    }
+
+    for (node, name, ty) in variable_values {
+        let variable = &variables[&name];
+        if !is_compatible(&ty, variable) {
+            diagnostics.push(Diagnostic::error(command, Some(&node.uid), format!(
+                "Variable \"{}\" has type {}, but this node uses it as {}", name, describe_type(variable), describe_type(&ty)
+            )));
+        }
+    }
+
+    bindings
+}
+
+/// Type an input gives to a type variable
+enum Candidate {
+    Known(NodeIOTy),
+    /// Input is connected to a generic output that isn't bound yet
+    Pending,
+    None,
+}
+
+/// Finds type of the first input of the node with the type variable that has a known type, in declaration order.
+/// Inputs without a value and inputs connected to outputs of type any are skipped, so are the pending ones if `skip_pending`
+fn input_candidate(
+    node: &DisbotterFlowNode,
+    node_ports: &NodePorts,
+    generic: &str,
+    sources: &HashMap<(&str, &str), &DisbotterFlowConnection>,
+    ports: &HashMap<&str, NodePorts>,
+    bindings: &Bindings,
+    skip_pending: bool,
+) -> Candidate {
+    for (key, instance) in node_ports.input_instances(node) {
+        if node_ports.inputs[key].generic.as_deref() != Some(generic) {
+            continue;
+        }
+        match sources.get(&(node.uid.as_str(), instance.as_str())) {
+            Some(conn) => {
+                let output = match ports.get(conn.from.as_str()).and_then(|from_ports| from_ports.outputs.get(&conn.from_key)) {
+                    Some(output) => bound_type(bindings, &conn.from, output),
+                    None => continue,
+                };
+                if output.ty != DataType::Any {
+                    return Candidate::Known(NodeIOTy { generic: None, ..output });
+                }
+                if output.generic.is_some() && !skip_pending {
+                    return Candidate::Pending;
+                }
+            },
+            None => {
+                if let Some(ty) = node.get_hardcoded(&instance).and_then(value_type) {
+                    return Candidate::Known(NodeIOTy { ty, ..Default::default() });
+                }
+            }
+        }
+    }
+    Candidate::None
+}
+
+/// Type of a port of the node with its type variable replaced by the bound type, other ports keep their type
+fn bound_type(bindings: &Bindings, uid: &str, port: &NodeIOTy) -> NodeIOTy {
+    match port.generic.as_ref().and_then(|generic| bindings.get(uid)?.get(generic)) {
+        Some(bound) => NodeIOTy { generic: port.generic.clone(), ..bound.clone() },
+        None => port.clone(),
+    }
+}
+
+/// Type of a value hardcoded in the editor
+fn value_type(value: &serde_json::Value) -> Option<DataType> {
+    match value {
+        serde_json::Value::String(_) => Some(DataType::Text),
+        serde_json::Value::Number(_) => Some(DataType::Number),
+        serde_json::Value::Bool(_) => Some(DataType::Boolean),
+        _ => None,
+    }
+}
+
+/// Ports of a node type, either declared by a node script or generated for special nodes
+struct NodePorts {
+    /// Input keys in declaration order
+    input_keys: Vec<String>,
+    inputs: HashMap<String, NodeIOTy>,
+    outputs: HashMap<String, NodeIOTy>,
+    /// Inputs that have a start value
+    defaults: Vec<String>,
+    /// Constraints of the inputs that have any
+    constraints: HashMap<String, InputConstraints>,
+    /// Inputs that can have any number of instances
+    variadic: Vec<String>,
+    /// Type variables of the ports, inputs first in declaration order
+    generics: Vec<String>,
+    /// Input with the name of the user variable the node works with
+    variable_input: Option<String>,
+    /// Values of different types for the same type variable are only a warning
+    lenient_types: bool,
+}
+
+impl NodePorts {
+    fn of(compiler: &NodesJSCompiler, command: &DisbotterProjectCommand, node_type: &str) -> Option<Self> {
+        if let Some(node) = compiler.registry().get(node_type) {
+            let metadata = &node.metadata;
+            let ports = |map: &HashMap<String, crate::loader::NodeIO>| map.iter()
+                .map(|(key, io)| (key.clone(), io.ty.clone()))
+                .collect::<HashMap<String, NodeIOTy>>();
+
+            return Some(Self {
+                input_keys: metadata.inputs.keys.clone(),
+                inputs: ports(&metadata.inputs.values),
+                outputs: ports(&metadata.outputs.values),
+                defaults: metadata.default_hardcoded.keys().cloned().collect(),
+                constraints: metadata.inputs.values.iter()
+                    .filter(|(_, io)| !io.constraints.is_empty())
+                    .map(|(key, io)| (key.clone(), io.constraints.clone()))
+                    .collect(),
+                variadic: metadata.inputs.values.iter()
+                    .filter(|(_, io)| io.variadic)
+                    .map(|(key, _)| key.clone())
+                    .collect(),
+                generics: {
+                    let mut generics = vec![];
+                    let inputs = metadata.inputs.keys.iter().filter_map(|key| metadata.inputs.values.get(key));
+                    let outputs = metadata.outputs.keys.iter().filter_map(|key| metadata.outputs.values.get(key));
+                    for generic in inputs.chain(outputs).filter_map(|io| io.ty.generic.as_ref()) {
+                        if !generics.contains(generic) {
+                            generics.push(generic.clone());
+                        }
+                    }
+                    generics
+                },
+                variable_input: metadata.variable_input.clone(),
+                lenient_types: metadata.lenient_types,
+            });
+        }
+
+        // Option nodes exist only for options of the command
+        let option_name = node_type.strip_prefix("___special_get_option_")?.strip_suffix("___")?;
+        let option = command.options.iter().find(|o| o.name == option_name)?;
+        let ty = match option.option_type {
+            0 => NodeIOTy { ty: DataType::Text, ..Default::default() },
+            1 => NodeIOTy { ty: DataType::Structure, struct_tags: vec!["user".to_string(), "user_resolvable".to_string()], ..Default::default() },
+            2 => NodeIOTy { ty: DataType::Structure, struct_tags: vec!["channel".to_string(), "guild_channel".to_string(), "text_channel".to_string()], ..Default::default() },
+            _ => return None,
+        };
+
+        Some(Self {
+            input_keys: vec![],
+            inputs: HashMap::new(),
+            outputs: HashMap::from([("value".to_string(), ty)]),
+            defaults: vec![],
+            constraints: HashMap::new(),
+            variadic: vec![],
+            generics: vec![],
+            variable_input: None,
+            lenient_types: false,
+        })
+    }
+
+    /// Value of a different type than the type variable of the node is bound to
+    fn type_mismatch(&self, command: &DisbotterProjectCommand, uid: &str, message: String) -> Diagnostic {
+        match self.lenient_types {
+            true => Diagnostic::warning(command, Some(uid), message),
+            false => Diagnostic::error(command, Some(uid), message),
+        }
+    }
+
+    fn is_variadic(&self, key: &str) -> bool {
+        self.variadic.iter().any(|variadic| variadic == key)
+    }
+
+    /// Finds input by the key used in connections, variadic inputs are connected only through
+    /// their instances, which exist up to the instance count of the node
+    fn input(&self, node: &DisbotterFlowNode, key: &str) -> Option<&NodeIOTy> {
+        match parse_variadic_key(key) {
+            Some((key, index)) if self.is_variadic(key) => {
+                self.inputs.get(key).filter(|_| index < node.input_count(key))
+            },
+            _ => self.inputs.get(key).filter(|_| !self.is_variadic(key)),
+        }
+    }
+
+    /// Keys of the declared inputs paired with keys of their instances in the node,
+    /// inputs that aren't variadic have a single instance with the same key
+    fn input_instances<'a>(&'a self, node: &DisbotterFlowNode) -> Vec<(&'a String, String)> {
+        self.input_keys.iter()
+            .flat_map(|key| match self.is_variadic(key) {
+                true => node.variadic_keys(key).into_iter().map(|instance| (key, instance)).collect(),
+                false => vec![(key, key.clone())],
+            })
+            .collect()
+    }
+}
+
+/// Checks if output can be connected to input, this is the same rule the editor uses.
+/// Types have to match unless one of them is any, structures need to have all tags the input requires
+fn is_compatible(output: &NodeIOTy, input: &NodeIOTy) -> bool {
+    match (output.ty, input.ty) {
+        (DataType::Any, _) | (_, DataType::Any) => true,
+        (DataType::Structure, DataType::Structure) => input.struct_tags.iter().all(|tag| output.struct_tags.contains(tag)),
+        (a, b) => a == b,
+    }
+}
+
+/// Describes type of a port, bound type variables are shown next to their type
+fn describe_type(ty: &NodeIOTy) -> String {
+    let name = match &ty.generic {
+        Some(generic) if ty.ty == DataType::Any => generic.clone(),
+        Some(generic) => format!("{} ({})", ty.ty.name(), generic),
+        None => ty.ty.name().to_string(),
+    };
+    if ty.struct_tags.is_empty() {
+        name
+    } else {
+        format!("{} [{}]", name, ty.struct_tags.join(", "))
+    }
+}
+
+/// Sorts counted ports, so diagnostics are always in the same order
+fn sorted<'a>(map: HashMap<(&'a str, &'a str), usize>) -> Vec<((&'a str, &'a str), usize)> {
+    let mut entries = map.into_iter().collect::<Vec<_>>();
+    entries.sort();
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::{json, Value};
+
+    use crate::{compiler::shared_engine, project::parse_project, registry::{NodeRegistry, RegisteredNode}};
+
+    use super::*;
+
+    /// Checks a project with a single command, `scripts` are node ids with the declarations of their ports.
+    /// Flow always has a start node with uid `start`
+    fn check(scripts: &[(&str, &str)], nodes: Value, connections: Value) -> Vec<Diagnostic> {
+        let dir = tempfile::tempdir().unwrap();
+        let start = (START_NODE, "const noFlowIn;\nconst inputs = #{};\nconst outputs = #{};");
+        let registry = NodeRegistry::from_nodes(scripts.iter().chain([&start]).map(|(id, declarations)| {
+            let path = dir.path().join(format!("{}.rhai", id));
+            std::fs::write(&path, format!(
+                "const id = \"{}\";\nconst title = \"{}\";\nconst description = \"\";\nconst category = \"Test\";\n{}\nfn action(builder) {{}}\n",
+                id, id, declarations
+            )).unwrap();
+            RegisteredNode::load(path, None, &shared_engine()).unwrap()
+        })).unwrap();
+
+        let mut nodes = nodes.as_array().unwrap().clone();
+        nodes.insert(0, json!({ "uid": "start", "type": START_NODE, "inputHardcoded": {} }));
+        let project = parse_project(json!({
+            "metadata": { "name": "test", "formatVersion": 2 },
+            "content": { "commands": [{
+                "uid": "c1", "name": "test", "description": "", "options": [],
+                "flow": { "nodes": nodes, "connections": connections },
+            }] },
+        })).unwrap();
+        let mut compiler = NodesJSCompiler::new(project);
+        compiler.set_registry(Arc::new(registry));
+        check_project_flows(&compiler)
+    }
+
+    /// Errors as `node uid: message`
+    fn errors(diagnostics: &[Diagnostic]) -> Vec<String> {
+        diagnostics.iter()
+            .filter(|d| d.is_error())
+            .map(|d| format!("{}: {}", d.node.as_deref().unwrap_or("-"), d.message))
+            .collect()
+    }
+
+    fn node(uid: &str, node_type: &str, hardcoded: Value) -> Value {
+        json!({ "uid": uid, "type": node_type, "inputHardcoded": hardcoded })
+    }
+
+    fn data(from: &str, from_key: &str, to: &str, to_key: &str) -> Value {
+        json!({ "type": 1, "from": from, "fromKey": from_key, "to": to, "toKey": to_key })
+    }
+
+    const NUMBER: (&str, &str) = ("number", "const pure;\nconst inputs = #{};\nconst outputs = #{ value: #{ name: \"Value\", type: \"number\", index: 0 } };");
+    const TEXT: (&str, &str) = ("text", "const pure;\nconst inputs = #{};\nconst outputs = #{ value: #{ name: \"Value\", type: \"text\", index: 0 } };");
+    const SHOW_NUMBER: (&str, &str) = ("show_number", "const pure;\nconst outputs = #{};\nconst inputs = #{ value: #{ name: \"Value\", type: \"number\", index: 0 } };");
+    const PICK: (&str, &str) = ("pick", "const pure;\nconst inputs = #{ a: #{ name: \"A\", type: \"T\", index: 0 }, b: #{ name: \"B\", type: \"T\", index: 1 } };\nconst outputs = #{ result: #{ name: \"Result\", type: \"T\" } };");
+    const COMPARE: (&str, &str) = ("compare", "const pure;\nconst lenientTypes;\nconst inputs = #{ a: #{ name: \"A\", type: \"T\", index: 0 }, b: #{ name: \"B\", type: \"T\", index: 1 } };\nconst outputs = #{ result: #{ name: \"Result\", type: \"boolean\" } };");
+    const DEFINE: (&str, &str) = ("define", "const variableInput = \"name\";\nconst inputs = #{ name: #{ name: \"Name\", type: \"text\", index: 0 }, value: #{ name: \"Value\", type: \"T\", index: 1 } };\nconst outputs = #{};");
+    const GET: (&str, &str) = ("get", "const pure;\nconst variableInput = \"name\";\nconst inputs = #{ name: #{ name: \"Name\", type: \"text\", index: 0 } };\nconst outputs = #{ value: #{ name: \"Value\", type: \"T\" } };");
+
+    #[test]
+    fn deprecated_nodes_are_warnings() {
+        let old = ("old_number", "const deprecated = \"number\";\nconst pure;\nconst inputs = #{};\nconst outputs = #{ value: #{ name: \"Value\", type: \"number\", index: 0 } };");
+        let gone = ("gone", "const deprecated;\nconst inputs = #{};\nconst outputs = #{};");
+        let diagnostics = check(&[NUMBER, old, gone, SHOW_NUMBER], json!([
+            node("o", "old_number", json!({})),
+            node("g", "gone", json!({})),
+            node("show", "show_number", json!({})),
+        ]), json!([data("o", "value", "show", "value"), { "type": 0, "from": "start", "fromKey": "__flow_out__", "to": "g", "toKey": "__flow_in__" }]));
+
+        assert!(errors(&diagnostics).is_empty(), "{:?}", errors(&diagnostics));
+        let warnings = diagnostics.iter()
+            .filter(|d| !d.is_error())
+            .map(|d| format!("{}: {}", d.node.as_deref().unwrap_or("-"), d.message))
+            .collect::<Vec<String>>();
+        assert!(warnings.contains(&"o: Node \"old_number\" (old_number) is deprecated, use \"number\" instead".to_string()), "{:?}", warnings);
+        assert!(warnings.contains(&"g: Node \"gone\" (gone) is deprecated".to_string()), "{:?}", warnings);
+    }
+
+    #[test]
+    fn binds_type_variables_from_hardcoded_values() {
+        let diagnostics = check(&[PICK, SHOW_NUMBER], json!([
+            node("numbers", "pick", json!({ "a": 1, "b": 2 })),
+            node("mixed", "pick", json!({ "a": 1, "b": "two" })),
+            node("show", "show_number", json!({})),
+        ]), json!([data("numbers", "result", "show", "value")]));
+
+        assert_eq!(errors(&diagnostics), vec!["mixed: Input \"b\" has a text value, but its type T is number"]);
+    }
+
+    #[test]
+    fn conflicts_dont_depend_on_connection_order() {
+        let nodes = json!([
+            node("n", "number", json!({})),
+            node("t", "text", json!({})),
+            node("p", "pick", json!({})),
+        ]);
+        let forward = json!([data("n", "value", "p", "a"), data("t", "value", "p", "b")]);
+        let backward = json!([data("t", "value", "p", "b"), data("n", "value", "p", "a")]);
+
+        // The first input in declaration order binds the variable
+        let expected = vec!["p: Input \"b\" of type number (T) can't be connected to output \"value\" of node t with type text"];
+        assert_eq!(errors(&check(&[NUMBER, TEXT, PICK], nodes.clone(), forward)), expected);
+        assert_eq!(errors(&check(&[NUMBER, TEXT, PICK], nodes, backward)), expected);
+    }
+
+    #[test]
+    fn types_pass_through_chains_of_generic_nodes() {
+        let nodes = json!([
+            node("show", "show_number", json!({})),
+            node("p3", "pick", json!({})),
+            node("p2", "pick", json!({})),
+            node("p1", "pick", json!({})),
+            node("t", "text", json!({})),
+        ]);
+        // Connections go against the chain, so every iteration binds only one more node
+        let connections = json!([
+            data("p3", "result", "show", "value"),
+            data("p2", "result", "p3", "a"),
+            data("p1", "result", "p2", "a"),
+            data("t", "value", "p1", "a"),
+        ]);
+        assert_eq!(errors(&check(&[TEXT, PICK, SHOW_NUMBER], nodes, connections)), vec![
+            "show: Input \"value\" of type number can't be connected to output \"result\" of node p3 with type text (T)",
+        ]);
+    }
+
+    #[test]
+    fn waits_for_generic_outputs_before_binding_later_inputs() {
+        // Input a of p2 comes from p1, which is bound only in the second iteration, it still wins over input b
+        let nodes = json!([
+            node("p2", "pick", json!({ "b": "text" })),
+            node("p1", "pick", json!({ "a": 1 })),
+        ]);
+        assert_eq!(errors(&check(&[PICK], nodes, json!([data("p1", "result", "p2", "a")]))), vec![
+            "p2: Input \"b\" has a text value, but its type T is number",
+        ]);
+    }
+
+    #[test]
+    fn variables_share_their_type() {
+        let diagnostics = check(&[DEFINE, GET, SHOW_NUMBER, TEXT], json!([
+            node("show", "show_number", json!({})),
+            node("get", "get", json!({ "name": "count" })),
+            node("define", "define", json!({ "name": "count", "value": 3 })),
+            node("redefine", "define", json!({ "name": "count" })),
+            node("t", "text", json!({})),
+            node("other", "define", json!({ "name": "other", "value": "text" })),
+            node("show_other", "show_number", json!({})),
+            node("get_other", "get", json!({ "name": "other" })),
+        ]), json!([
+            data("get", "value", "show", "value"),
+            data("t", "value", "redefine", "value"),
+            data("get_other", "value", "show_other", "value"),
+        ]));
+
+        assert_eq!(errors(&diagnostics), vec![
+            "redefine: Variable \"count\" has type number, but this node uses it as text",
+            "show_other: Input \"value\" of type number can't be connected to output \"value\" of node get_other with type text (T)",
+        ]);
+    }
+
+    #[test]
+    fn lenient_nodes_only_warn_about_mixed_types() {
+        let diagnostics = check(&[COMPARE, NUMBER], json!([
+            node("n", "number", json!({})),
+            node("c", "compare", json!({ "b": "text" })),
+        ]), json!([data("n", "value", "c", "a")]));
+
+        assert!(errors(&diagnostics).is_empty());
+        assert!(diagnostics.iter().any(|d| d.node.as_deref() == Some("c") && d.message == "Input \"b\" has a text value, but its type T is number"));
+    }
+
+    #[test]
+    fn constraints_apply_to_hardcoded_values_of_unconnected_inputs() {
+        let limited = ("limited", "const pure;\nconst outputs = #{};\nconst inputs = #{ value: #{ name: \"Value\", type: \"number\", index: 0, min: 1, required: true } };");
+        let diagnostics = check(&[NUMBER, limited], json!([
+            node("low", "limited", json!({ "value": 0 })),
+            node("ok", "limited", json!({ "value": 1 })),
+            node("missing", "limited", json!({})),
+            node("connected", "limited", json!({ "value": 0 })),
+            node("source", "number", json!({})),
+        ]), json!([data("source", "value", "connected", "value")]));
+
+        assert_eq!(errors(&diagnostics), vec![
+            "low: Input \"value\": 0 is less than the minimum 1",
+            "missing: Input \"value\": input is required, connect it or set its value",
+        ]);
+    }
+}
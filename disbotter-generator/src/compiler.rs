@@ -1,8 +1,12 @@
-use std::{collections::HashMap, path::PathBuf, sync::{Arc, Mutex}, fmt::{Display, Debug}};
-use rhai::{Engine, Scope, EvalContext, Expression, EvalAltResult, Dynamic};
+use std::{collections::{HashSet, BTreeSet}, path::PathBuf, sync::{Arc, Mutex, OnceLock, atomic::{AtomicUsize, Ordering}}, fmt::{Display, Debug}};
+use rhai::{Engine, EvalContext, Expression, EvalAltResult, Dynamic};
+use rayon::prelude::*;
 use colored::*;
 
-use crate::builder::{CodeBuilder, Program};
+use crate::{builder::{CodeBuilder, Program, RegistryEntry, RegistryEntryKind}, registry::NodeRegistry, typescript::TsType, graph::FlowGraph, scope::VarScope, project::{ProjectLoadError, DisbotterProjectData, DisbotterProjectCommand, DisbotterFlowNode, START_NODE}, cache::{CompileCache, CachedCommand, hash_bytes}};
+
+/// Path of the generated registry module, relative to the output directory
+pub const REGISTRY_PATH: &str = "registry.ts";
 
 /// Represents a port on a node, it consists of a node uid and a port key
 /// There are also global ports, which are just a key
@@ -34,13 +38,17 @@ impl Clone for NodesJSCompiler {
 
 /// Compiler for the nodes
 pub struct NodesJSCompiler {
-    available_nodes: HashMap<String, AvailableNode>,
+    /// Nodes that can be used in the project, shared by all compilers
+    nodes: Arc<NodeRegistry>,
     project: Arc<DisbotterProjectData>,
-    pub var_cache: Arc<Mutex<HashMap<PortIdentifier, String>>>,
-    engine: Engine,
+    pub var_cache: Arc<Mutex<VarScope>>,
+    engine: Arc<Engine>,
     program: Program,
-    pub current_flow: Option<DisbotterFlow>,
+    pub current_flow: Option<Arc<FlowGraph>>,
     current_command: Option<DisbotterProjectCommand>,
+    cache: Option<Arc<dyn CompileCache>>,
+    /// Counter used to generate unique variable names, shared by all compilers of a command
+    var_counter: Arc<AtomicUsize>,
 }
 
 /// all possible errors that can occur during compilation
@@ -48,6 +56,7 @@ pub enum CompilerError {
     RhaiError(Box<EvalAltResult>),
     InvalidPortIdentifier(PortIdentifier),
     NodeNotFound(String),
+    NodeUidNotFound(String),
     BadContext(String),
     NoStartNode,
 }
@@ -64,6 +73,9 @@ impl CompilerError {
             CompilerError::NodeNotFound(node_id) => {
                 format!("{}: {}", "Node not found".red(), node_id)
             }
+            CompilerError::NodeUidNotFound(node_uid) => {
+                format!("{}: {}", "Node with uid not in flow".red(), node_uid)
+            }
             CompilerError::NoStartNode => {
                 format!("{}: {}", "No start node".red(), "No start node found")
             }
@@ -102,82 +114,224 @@ impl Display for PortIdentifier {
 impl NodesJSCompiler {
     /// Create a new compiler
     pub fn new(project: DisbotterProjectData) -> NodesJSCompiler {
-        let mut engine = Engine::new();
-        upgrade_engine(&mut engine);
-
         NodesJSCompiler {
-            available_nodes: HashMap::new(),
+            nodes: Arc::new(NodeRegistry::new()),
             project: Arc::new(project),
-            var_cache: Arc::new(Mutex::new(HashMap::new())),
-            engine,
+            var_cache: Arc::new(Mutex::new(VarScope::new())),
+            engine: shared_engine(),
             program: Program::new(),
             current_flow: None,
             current_command: None,
+            cache: None,
+            var_counter: Arc::new(AtomicUsize::new(0)),
         }
     }
 
     /// Clones the compiler, used for nodes like the if node
     /// that need to compile their own code
     pub fn get_cloned_compiler(&self) -> NodesJSCompiler {
-        let mut engine = Engine::new();
-        upgrade_engine(&mut engine);
-
         NodesJSCompiler {
-            available_nodes: self.available_nodes.clone(),
+            nodes: Arc::clone(&self.nodes),
             project: Arc::clone(&self.project),
             var_cache: Arc::clone(&self.var_cache),
-            engine,
+            engine: Arc::clone(&self.engine),
             program: Program::new(),
             current_flow: self.current_flow.clone(),
             current_command: self.current_command.clone(),
+            cache: self.cache.clone(),
+            var_counter: Arc::clone(&self.var_counter),
         }
     }
 
-    /// Generates a random variable name.
-    /// Variable names are just two first parts of a v4 uuid combined.
-    /// It is useful to prefix variable with for example underscore to ensure
-    /// they don't start with a number
-    pub(crate) fn random_var_name() -> String {
-        let id = uuid::Uuid::new_v4();
-        let id = id.to_string();
-        let id = id.split("-");
-        // First two parts of the uuid combined should be enough to be unique
-        let id = format!("{}{}", id.clone().next().unwrap(), id.clone().next().unwrap());
-        format!("{}", id)
+    /// Creates compiler with its own var cache, program and variable counter,
+    /// so that commands can be compiled independently of each other
+    fn get_command_compiler(&self) -> NodesJSCompiler {
+        let mut compiler = self.get_cloned_compiler();
+        compiler.var_cache = Arc::new(Mutex::new(VarScope::new()));
+        compiler.var_counter = Arc::new(AtomicUsize::new(0));
+        compiler
     }
 
-    /// Loads a project from a path
-    pub fn load_project(path: PathBuf) -> DisbotterProjectData {
-        let file = std::fs::read_to_string(path).unwrap();
-        let project: DisbotterProjectData = serde_json::from_str(&file).unwrap();
-        project
+    /// Generates a unique variable name.
+    /// Names are numbered in the order they are requested, so the same command
+    /// always compiles to the same code. It is useful to prefix variable
+    /// with for example underscore to ensure they don't start with a number
+    pub(crate) fn next_var_name(&self) -> String {
+        self.var_counter.fetch_add(1, Ordering::Relaxed).to_string()
     }
 
-    /// Adds available nodes from a path
-    pub fn add_available_nodes(&mut self, path: PathBuf) {
-        let nodes = AvailableNode::load_nodes(path, &mut self.engine);
-        for node in nodes {
-            self.available_nodes.insert(node.id.clone(), node);
-        }
+    /// Loads project from a file, older projects are upgraded to the current format
+    pub fn load_project(path: PathBuf) -> Result<DisbotterProjectData, ProjectLoadError> {
+        crate::project::load_project(&path)
     }
 
-    /// Adds available nodes from vector
-    pub fn add_available_nodes_from_vec(&mut self, nodes: &Vec<AvailableNode>) {
-        for node in nodes {
-            self.available_nodes.insert(node.id.clone(), node.clone());
-        }
+    /// Gets the project being compiled
+    pub fn project(&self) -> &DisbotterProjectData {
+        &self.project
+    }
+
+    /// Sets nodes that can be used in the project
+    pub fn set_registry(&mut self, nodes: Arc<NodeRegistry>) {
+        self.nodes = nodes;
+    }
+
+    /// Gets nodes that can be used in the project
+    pub fn registry(&self) -> &NodeRegistry {
+        &self.nodes
+    }
+
+    /// Sets cache used to reuse outputs of commands that didn't change
+    pub fn set_cache(&mut self, cache: Arc<dyn CompileCache>) {
+        self.cache = Some(cache);
     }
 
     /// Compiles the project.
     /// This means it will compile all commands, events, etc.
+    /// Commands are compiled in parallel, each in its own context, and merged in the original order,
+    /// so the output is the same as if they were compiled one after another
     pub fn compile_project(mut self) -> Result<Program, CompilerError> {
-        for command in self.project.clone().content.commands.iter() {
-            self.compile_command(&command)?;
+        let project = Arc::clone(&self.project);
+        let results = project.content.commands.par_iter()
+            .map(|command| self.compile_command_in_context(command))
+            .collect::<Vec<Result<(Program, Option<String>), CompilerError>>>();
+
+        let mut cache_keys = HashSet::new();
+        // Errors are checked in order, so the reported error is the same as in a sequential build
+        for result in results {
+            let (program, cache_key) = result?;
+            self.program.extend(program);
+            cache_keys.extend(cache_key);
+        }
+        if let Some(cache) = &self.cache {
+            cache.retain(&cache_keys);
         }
+        self.compile_registry();
 
         Ok(self.program)
     }
 
+    /// Compiles specified command in a new context and returns program containing only this command.
+    /// If there is a cache, the output is reused when nothing the command depends on changed,
+    /// cache key of the command is returned in that case
+    fn compile_command_in_context(&self, command: &DisbotterProjectCommand) -> Result<(Program, Option<String>), CompilerError> {
+        let mut compiler = self.get_command_compiler();
+
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => {
+                compiler.compile_command(command)?;
+                return Ok((compiler.program, None));
+            }
+        };
+
+        let key = self.command_cache_key(command);
+        if let Some(cached) = cache.get(&key) {
+            for (path, code) in cached.files {
+                compiler.program.add_file(path, code);
+            }
+            for entry in cached.registry {
+                compiler.program.register(entry);
+            }
+            log::info!("Reused cached command {}", command.name);
+            return Ok((compiler.program, Some(key)));
+        }
+
+        compiler.compile_command(command)?;
+        cache.put(&key, CachedCommand {
+            files: compiler.program.files.iter()
+                .map(|file| {
+                    let file = file.lock().unwrap();
+                    (file.path.clone(), file.code.clone())
+                })
+                .collect(),
+            registry: compiler.program.registry.clone(),
+        });
+
+        Ok((compiler.program, Some(key)))
+    }
+
+    /// Compiles specified command in a new context without using the cache and throws the output away,
+    /// used to find errors in node actions without writing anything
+    pub fn dry_run_command(&self, command: &DisbotterProjectCommand) -> Result<(), CompilerError> {
+        let mut compiler = self.get_command_compiler();
+        compiler.cache = None;
+        compiler.compile_command(command)
+    }
+
+    /// Computes cache key of a command.
+    /// It covers the command itself (flow, options, name, ...), scripts of all nodes used in its flow
+    /// and version of the generator
+    fn command_cache_key(&self, command: &DisbotterProjectCommand) -> String {
+        // Maps in the project keep the order from the file, so the same command always gives the same data
+        let mut data = serde_json::to_vec(command).unwrap();
+
+        let node_types = command.flow.nodes.iter()
+            .map(|node| node.node_type.as_str())
+            .collect::<BTreeSet<&str>>();
+        for node_type in node_types {
+            if let Some(node) = self.nodes.get(node_type) {
+                data.extend_from_slice(node_type.as_bytes());
+                data.extend_from_slice(node.source_hash.as_bytes());
+            }
+        }
+
+        data.extend_from_slice(env!("CARGO_PKG_VERSION").as_bytes());
+        hash_bytes(&data)
+    }
+
+    /// Generates the registry module, which imports and exports all compiled handlers
+    /// together with their metadata, so the runtime doesn't have to scan directories for them
+    pub fn compile_registry(&mut self) {
+        let sections = [
+            (RegistryEntryKind::Command, "commands", "typeof Command"),
+        ];
+
+        let mut imports = vec![
+            "import type { Command } from \"disbotter\";".to_string(),
+        ];
+        let mut code = vec![
+            "".to_string(),
+            "export interface RegistryEntry<T> {".to_string(),
+            "    name: string;".to_string(),
+            "    description: string;".to_string(),
+            "    file: string;".to_string(),
+            "    handler: T;".to_string(),
+            "}".to_string(),
+        ];
+
+        for (kind, name, handler_type) in sections {
+            let entries = self.program.registry.iter()
+                .filter(|e| e.kind == kind)
+                .collect::<Vec<&RegistryEntry>>();
+
+            code.push("".to_string());
+            if entries.is_empty() {
+                code.push(format!("export const {}: RegistryEntry<{}>[] = [];", name, handler_type));
+                continue;
+            }
+
+            code.push(format!("export const {}: RegistryEntry<{}>[] = [", name, handler_type));
+            for (i, entry) in entries.iter().enumerate() {
+                let handler = format!("__{}_{}", name, i);
+                let module = format!("./{}", entry.path.trim_end_matches(".ts"));
+                imports.push(format!("import {} from {};", handler, js_string(&module)));
+                code.push(format!(
+                    "    {{ name: {}, description: {}, file: {}, handler: {} }},",
+                    js_string(&entry.name), js_string(&entry.description), js_string(&entry.path), handler
+                ));
+            }
+            code.push("];".to_string());
+        }
+
+        code.push("".to_string());
+        code.push(format!("export default {{ {} }};", sections.map(|(_, name, _)| name).join(", ")));
+
+        imports.extend(code);
+        let mut code = imports.join("\n");
+        code.push('\n');
+        self.program.add_file(REGISTRY_PATH.to_string(), code);
+    }
+
     /// Adds a global variable to the var cache
     pub fn add_var(&mut self, var_key: String, var_name: String) {
         self.var_cache.lock().unwrap().insert(PortIdentifier::Global { key: var_key }, var_name);
@@ -190,11 +344,19 @@ impl NodesJSCompiler {
 
     /// Compiles specified command
     pub fn compile_command(&mut self, command: &DisbotterProjectCommand) -> Result<(), CompilerError> {
+        // Command has to be set before the builder clones the compiler
+        self.current_command = Some(command.clone());
+
         // Get builder
-        let mut builder = self.program.get_file_builder(format!("commands/{}.ts", command.name), &self.var_cache, self.get_cloned_compiler());
+        let path = format!("commands/{}.ts", command.name);
+        let mut builder = self.program.get_file_builder(path.clone(), &self.var_cache, self.get_cloned_compiler());
+        self.program.register(RegistryEntry {
+            kind: RegistryEntryKind::Command,
+            name: command.name.clone(),
+            description: command.description.clone(),
+            path,
+        });
 
-        self.current_command = Some(command.clone());
-        
         // Boilerplate
         builder.add_lines(vec![
             "export default class extends Command {".to_string(),
@@ -228,12 +390,13 @@ impl NodesJSCompiler {
         // More boilerplate
         builder.add_lines(vec![
             "".to_string(),
-            "   public async handle(__TRANSLATIONS__: LocalizedTranslations, __INTERACTION__: CommandInteraction): Promise<void> {".to_string(),
+            "   public async handle(__TRANSLATIONS__: LocalizedTranslations, __INTERACTION__: ChatInputCommandInteraction): Promise<void> {".to_string(),
         ]);
         builder.increase_ident_by(2);
 
-        // Compile flow
-        self.compile_flow(&command.flow, builder.clone(), "__start__")?;
+        // Compile flow, values from the editor were already checked against the constraints by `check_project_flows`
+        let graph = Arc::new(FlowGraph::new(Arc::clone(&command.flow))?);
+        self.compile_flow(&graph, builder.clone(), START_NODE)?;
 
         // More boilerplate
         builder.decrease_ident_by(2);
@@ -241,45 +404,17 @@ impl NodesJSCompiler {
             "   }".to_string(),
             "}".to_string(),
         ]);
-        builder.add_import("CommandInteraction, SlashCommandBuilder".to_string(), "discord.js".to_string());
+        builder.add_import("ChatInputCommandInteraction, SlashCommandBuilder".to_string(), "discord.js".to_string());
         builder.add_import("Command, LocalizedTranslations".to_string(), "disbotter".to_string());
-        
-        builder.add_on_top("// @ts-nocheck".to_string());
-        builder.add_on_top("// This file is automatically generated by Disbotter".to_string());
         builder.finalize();
 
         Ok(())
     }
 
-    /// Get whatever port is connected to
-    pub fn get_flow_target(&self, flow: &DisbotterFlow, port: PortIdentifier) -> Option<PortIdentifier> {
-        match port {
-            PortIdentifier::Input { node_uid, port_key } => {
-                let conn = flow.connections.iter()
-                    .find(|c| c.to == node_uid && c.to_key == port_key);
-                if let Some(conn) = conn {
-                    Some(PortIdentifier::Output { node_uid: conn.from.clone(), port_key: conn.from_key.clone() })
-                } else {
-                    None
-                }
-            },
-            PortIdentifier::Output { node_uid, port_key } => {
-                let conn = flow.connections.iter()
-                    .find(|c| c.from == node_uid && c.from_key == port_key);
-                if let Some(conn) = conn {
-                    Some(PortIdentifier::Input { node_uid: conn.to.clone(), port_key: conn.to_key.clone() })
-                } else {
-                    None
-                }
-            },
-            _ => None
-        }
-    }
-
     /// Gets global variable
     pub fn get_global_var(&self, key: String) -> Option<String> {
         self.var_cache.lock().unwrap().get(&PortIdentifier::Global { key })
-            .map(|v| v.clone())
+            .cloned()
     }
 
     /// Some nodes need to be compiled differently, for example option nodes, these nodes are compiled here
@@ -301,30 +436,40 @@ impl NodesJSCompiler {
                 }
 
                 // Get option type
-                let opt_type = match self.current_command.as_ref().unwrap().options.iter().find(|o| o.name == option_name) {
-                    Some(opt) => {
-                        match opt.option_type {
-                            0 => "String",
-                            1 => "User",
-                            2 => "Channel",
-                            _ => {
-                                return Err(CompilerError::BadContext(format!("Unknown option type: {}", opt.option_type)));
-                            }
-                        }
-                    },
+                let option = match self.current_command.as_ref().unwrap().options.iter().find(|o| o.name == option_name) {
+                    Some(option) => option,
                     None => {
                         return Err(CompilerError::BadContext(format!("Cannot get option \"{}\" because it does not exist", option_name)));
                     }
                 };
+                // Channel getter returns a union that also contains raw API channels, so its type is left to inference
+                let (opt_type, ts_type) = match option.option_type {
+                    0 => ("String", Some(TsType::builtin("string"))),
+                    1 => ("User", Some(TsType::imported("User", "discord.js"))),
+                    2 => ("Channel", None),
+                    _ => {
+                        return Err(CompilerError::BadContext(format!("Unknown option type: {}", option.option_type)));
+                    }
+                };
 
                 // Create variable for option
+                // Required options are never null, so the getter is told about it to narrow the type
                 let option_var_name = format!("__get_option_{}", option_name);
-                builder.add_line(format!("let {} = {}.options.get{}(\"{}\");", option_var_name, interaction_name.unwrap(), opt_type, option_name));
+                builder.add_line(format!("let {} = {}.options.get{}(\"{}\", {});", option_var_name, interaction_name.unwrap(), opt_type, option_name, option.required));
+
+                let ts_type = ts_type.map(|ts_type| if option.required {
+                    ts_type
+                } else {
+                    TsType { name: format!("{} | null", ts_type.name), import: ts_type.import }
+                });
 
                 builder.var_cache.lock().unwrap().insert(
                     PortIdentifier::Output { node_uid: node.uid.clone(), port_key: "value".to_string() },
                     option_var_name.clone()
                 );
+                if let Some(ts_type) = ts_type {
+                    builder.set_port_ts_type(node.get_port_out("value"), ts_type);
+                }
 
                 return Ok(true);
             }
@@ -333,11 +478,9 @@ impl NodesJSCompiler {
     }
 
     /// Map the outputs of other nodes to the inputs of the current node
-    pub fn map_node_inputs(&mut self, flow: &DisbotterFlow, node: &DisbotterFlowNode, builder: CodeBuilder) -> Result<(), CompilerError> {
+    pub fn map_node_inputs(&mut self, flow: &FlowGraph, node: &DisbotterFlowNode, builder: CodeBuilder) -> Result<(), CompilerError> {
         // Iterate over all inputs
-        let node_connections = flow.connections.iter()
-            .filter(|c| c.to == node.uid)
-            .collect::<Vec<&DisbotterFlowConnection>>();
+        let node_connections = flow.get_node_inputs(&node.uid);
 
         let var_cache_c = self.var_cache.clone();
         let mut var_cache = var_cache_c.lock().unwrap();
@@ -351,19 +494,17 @@ impl NodesJSCompiler {
                 var_cache.insert(PortIdentifier::Input { node_uid: conn.to.clone(), port_key: conn.to_key.clone() }, existing_data);
             } else {
                 // Check if the node here is connected to a pure function
-                let from_node = flow.nodes.iter()
-                    .find(|n| n.uid == conn.from)
-                    .unwrap();
+                let from_node = flow.get_node(&conn.from)?;
 
-                let from_node_template = self.available_nodes.get(&from_node.node_type);
+                let from_node_template = self.nodes.get(&from_node.node_type);
                 if from_node_template.is_none() {
                     drop(var_cache);
                     if self.compile_special_node(builder.clone(), from_node)? {
                         var_cache = var_cache_c.lock().unwrap();
                         // Bind the output to the input
-                        let new_data = var_cache
-                            .get(&PortIdentifier::Output { node_uid: conn.from.clone(), port_key: conn.from_key.clone() })
-                            .unwrap()
+                        let output = from_node.get_port_out(&conn.from_key);
+                        let new_data = var_cache.get(&output)
+                            .ok_or_else(|| CompilerError::InvalidPortIdentifier(output.clone()))?
                             .clone();
                         var_cache.insert(PortIdentifier::Input { node_uid: conn.to.clone(), port_key: conn.to_key.clone() }, new_data);
                         continue;
@@ -379,9 +520,9 @@ impl NodesJSCompiler {
                     var_cache = var_cache_c.lock().unwrap();
 
                     // Bind the output to the input
-                    let new_data = var_cache
-                        .get(&PortIdentifier::Output { node_uid: conn.from.clone(), port_key: conn.from_key.clone() })
-                        .unwrap()
+                    let output = from_node.get_port_out(&conn.from_key);
+                    let new_data = var_cache.get(&output)
+                        .ok_or_else(|| CompilerError::InvalidPortIdentifier(output.clone()))?
                         .clone();
                     var_cache.insert(PortIdentifier::Input { node_uid: conn.to.clone(), port_key: conn.to_key.clone() }, new_data);
                 }
@@ -391,17 +532,14 @@ impl NodesJSCompiler {
         // Map hardcoded inputs
         for (key, value) in node.input_hardcoded.iter() {
             // If input is not mapped already
-            if !var_cache.contains_key(&PortIdentifier::Input { node_uid: node.uid.clone(), port_key: key.clone() }) {
-                // Map
-                var_cache.insert(PortIdentifier::Input { node_uid: node.uid.clone(), port_key: key.clone() }, get_raw_value(value));
-            }
+            var_cache.insert_if_missing(PortIdentifier::Input { node_uid: node.uid.clone(), port_key: key.clone() }, || get_raw_value(value));
         }
 
         Ok(())
     }
 
     /// Compiles flow starting from specified node
-    pub fn compile_flow_from_port(&mut self, flow: &DisbotterFlow, builder: CodeBuilder, port: PortIdentifier, node: &DisbotterFlowNode)
+    pub fn compile_flow_from_port(&mut self, flow: &FlowGraph, builder: CodeBuilder, port: PortIdentifier, node: &DisbotterFlowNode)
         -> Result<(), CompilerError> {
         let oport_key = match &port {
             PortIdentifier::Output { node_uid: _, port_key } => Ok(port_key),
@@ -409,11 +547,11 @@ impl NodesJSCompiler {
         }?;
 
         let mut current_flow_out: Option<PortIdentifier> = 
-            Some(node.get_port_out(&oport_key));
+            Some(node.get_port_out(oport_key));
 
         // Compile node that is connected to the start node
         while let Some(flow_out) = current_flow_out {
-            let target = self.get_flow_target(flow, flow_out.clone());
+            let target = flow.get_target(&flow_out);
             if target.is_none() {
                 break;
             }
@@ -421,8 +559,7 @@ impl NodesJSCompiler {
                 if port_key != "__flow_in__" {
                     return Err(CompilerError::InvalidPortIdentifier(port.clone()));
                 }
-                let node = flow.nodes.iter().find(|n| n.uid == node_uid).unwrap();
-
+                let node = flow.get_node(&node_uid)?;
 
                 self.compile_node(node, flow, builder.clone())?;
                 current_flow_out = Some(node.get_port_out("__flow_out__"));
@@ -435,10 +572,10 @@ impl NodesJSCompiler {
     }
 
     /// Find node that has id equal to specified id
-    pub fn compile_flow(&mut self, flow: &DisbotterFlow, mut builder: CodeBuilder, start_node_id: &str) -> Result<(), CompilerError> {
-        builder.compiler.current_flow = Some(flow.clone());
+    pub fn compile_flow(&mut self, flow: &Arc<FlowGraph>, mut builder: CodeBuilder, start_node_id: &str) -> Result<(), CompilerError> {
+        builder.compiler.current_flow = Some(Arc::clone(flow));
         // Find the start node
-        let start_node = flow.nodes.iter().find(|n| n.node_type == start_node_id);
+        let start_node = flow.find_node_of_type(start_node_id);
         if start_node.is_none() {
             return Err(CompilerError::NoStartNode);
         }
@@ -447,7 +584,8 @@ impl NodesJSCompiler {
         self.clear_var_cache();
         // Add interaction key
         self.add_var("___interaction".to_string(), "__INTERACTION__".to_string());
-        self.add_var("___guild".to_string() , "__INTERACTION__.guild".to_string());
+        // Guild is nullable in discord.js typings, but nodes using it only make sense in guilds
+        self.add_var("___guild".to_string() , "__INTERACTION__.guild!".to_string());
         self.add_var("___translations".to_string(), "__TRANSLATIONS__".to_string());
 
         // Compile the start node
@@ -459,18 +597,18 @@ impl NodesJSCompiler {
         Ok(())
     }
 
-    pub fn compile_node(&mut self, node: &DisbotterFlowNode, flow: &DisbotterFlow, mut builder: CodeBuilder) -> Result<(), CompilerError> {
+    pub fn compile_node(&mut self, node: &DisbotterFlowNode, flow: &FlowGraph, mut builder: CodeBuilder) -> Result<(), CompilerError> {
         self.map_node_inputs(flow, node, builder.clone())?;
         let node_type = &node.node_type;
         let node_id = &node.uid;
-        let node = self.available_nodes.get(node_type);
+        let node = self.nodes.get(node_type);
         if node.is_none() {
             return Err(CompilerError::NodeNotFound(node_type.clone()));
         }
         let node = node.unwrap();
         builder.current_node_id = node_id.clone();
-        node.call_action(&mut self.engine, builder.clone())?;
-        println!("{} {} ({})", "Compiled node".green(), node.id, node_id);
+        node.call_action(&self.engine, builder.clone())?;
+        log::debug!("Compiled node {} ({})", node.id(), node_id);
 
         Ok(())
     }
@@ -500,6 +638,11 @@ fn expr_shortcut_set_output(context: &mut EvalContext, inputs: &[Expression]) ->
     Ok(Dynamic::from(()))
 }
 
+/// Converts text to a JavaScript string literal
+fn js_string(text: &str) -> String {
+    serde_json::to_string(text).unwrap()
+}
+
 fn get_raw_value(value: &serde_json::Value) -> String {
     match value {
         serde_json::Value::String(s) => format!("\"{}\"", s),
@@ -509,6 +652,21 @@ fn get_raw_value(value: &serde_json::Value) -> String {
     }
 }
 
+/// Returns the engine shared by all compilers and loaders.
+/// Engine is built only once and never modified afterwards, all state needed
+/// while running a script (like the builder) is passed through the scope
+pub fn shared_engine() -> Arc<Engine> {
+    static ENGINE: OnceLock<Arc<Engine>> = OnceLock::new();
+
+    Arc::clone(ENGINE.get_or_init(|| {
+        let mut engine = Engine::new();
+        upgrade_engine(&mut engine);
+        // Node scripts are optimized after their metadata are read, see `RegisteredNode::load`
+        engine.set_optimization_level(rhai::OptimizationLevel::None);
+        Arc::new(engine)
+    }))
+}
+
 pub fn upgrade_engine(engine: &mut Engine) {
     // CodeBuilder Type
     engine.build_type::<CodeBuilder>();
@@ -540,130 +698,216 @@ pub fn upgrade_engine(engine: &mut Engine) {
     ).ok();
 }
 
-#[derive(Clone)]
-pub struct AvailableNode {
-    pub id: String,
-    ast: Arc<rhai::AST>,
-    pub is_pure: bool
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::RegisteredNode;
 
-impl AvailableNode {
-    pub fn call_action(&self, engine: &mut Engine, builder: CodeBuilder) -> Result<(), CompilerError> {
-        let mut scope = Scope::new();
-        engine.call_fn::<()>(&mut scope, &self.ast, "action", (builder,)).map_err(|e| CompilerError::RhaiError(e))?;
-        Ok(())
+    fn write_script(dir: &std::path::Path, id: &str, action: &str) {
+        write_node(dir, id, "const inputs = #{};\nconst outputs = #{};", action);
     }
 
-    pub fn load_nodes(path: PathBuf, engine: &mut Engine) -> Vec<AvailableNode> {
-        let mut nodes = Vec::new();
-        let files = std::fs::read_dir(path).unwrap();
-        for file in files {
-            let file = file.unwrap();
-            let file_path = file.path();
-            if file_path.is_file() {
-                let file_name = file_path.file_name().unwrap().to_str().unwrap();
-                if file_name.ends_with(".rhai") {
-                    let file = std::fs::read_to_string(file_path).unwrap();
-                    let ast = engine.compile(&file).unwrap();
-                    let mut constants = ast.iter_literal_variables(true, false);
-                    let id = constants.find(|c| c.0 == "id").expect("All nodes should have an id").2.clone_cast::<String>();
-                    let is_pure = constants.find(|c| c.0 == "pure").is_some();
-                    drop(constants); // Drop the iterator so we can use the AST
-                    nodes.push(AvailableNode {
-                        id,
-                        ast: Arc::new(ast),
-                        is_pure
-                    });
-                }
-            } else if file_path.is_dir() {
-                nodes.append(&mut Self::load_nodes(file_path, engine));
-            }
-        }
-        nodes
+    fn write_node(dir: &std::path::Path, id: &str, declarations: &str, action: &str) {
+        let script = format!(
+            "const id = \"{id}\";\nconst title = \"{id}\";\nconst description = \"\";\nconst category = \"Test\";\n\
+            {declarations}\nfn action(builder) {{ {action} }}\n"
+        );
+        std::fs::write(dir.join(format!("{}.rhai", id)), script).unwrap();
     }
-}
-
-// ===< Data structures >=== //
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct DisbotterProjectData {
-    pub metadata: DisbotterProjectMetadata,
-    pub content: DisbotterProjectContent
-}
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct DisbotterProjectMetadata {
-    pub name: String,
-}
-
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct DisbotterProjectContent {
-    pub commands: Vec<DisbotterProjectCommand>
-}
-
-#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
-pub struct DisbotterProjectCommand {
-    uid: String,
-    name: String,
-    description: String,
-    flow: DisbotterFlow,
-    options: Vec<DisbotterProjectCommandOption>
-}
-
-#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
-pub struct DisbotterProjectCommandOption {
-    name: String,
-    description: String,
-    #[serde(rename = "type")]
-    option_type: i32,
-    required: bool,
-    choices: Vec<String>
-}
+    /// Cache key of the only command of a project using node "used", with scripts loaded from the directory
+    fn cache_key(dir: &std::path::Path, name: &str) -> String {
+        let nodes = ["used", "unused"].map(|id| RegisteredNode::load(dir.join(format!("{}.rhai", id)), None, &shared_engine()).unwrap());
+        let project = serde_json::from_value(serde_json::json!({
+            "metadata": { "name": "test", "formatVersion": 2 },
+            "content": { "commands": [{
+                "uid": "c1",
+                "name": name,
+                "description": "",
+                "options": [],
+                "flow": {
+                    "nodes": [{ "uid": "n1", "type": "used", "inputHardcoded": {} }],
+                    "connections": [],
+                },
+            }] },
+        })).unwrap();
+        let mut compiler = NodesJSCompiler::new(project);
+        compiler.set_registry(Arc::new(NodeRegistry::from_nodes(nodes).unwrap()));
+        compiler.command_cache_key(&compiler.project().content.commands[0])
+    }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
-pub struct DisbotterFlow {
-    nodes: Vec<DisbotterFlowNode>,
-    connections: Vec<DisbotterFlowConnection>
-}
+    /// Compiles a project with a single command using nodes loaded from the directory,
+    /// the directory also gets the start node. Returns code of the command
+    fn compile(dir: &std::path::Path, nodes: serde_json::Value, connections: serde_json::Value) -> String {
+        write_script(dir, START_NODE, "");
+        let mut nodes = nodes.as_array().unwrap().clone();
+        nodes.insert(0, serde_json::json!({ "uid": "start", "type": START_NODE, "inputHardcoded": {} }));
+        let project = serde_json::from_value(serde_json::json!({
+            "metadata": { "name": "test", "formatVersion": 2 },
+            "content": { "commands": [{
+                "uid": "c1", "name": "test", "description": "", "options": [],
+                "flow": { "nodes": nodes, "connections": connections },
+            }] },
+        })).unwrap();
+        let mut compiler = NodesJSCompiler::new(project);
+        compiler.set_registry(Arc::new(NodeRegistry::load(&[dir.to_path_buf()], &shared_engine()).unwrap()));
+
+        let program = compiler.compile_project().unwrap();
+        let code = program.files[0].lock().unwrap().code.clone();
+        code
+    }
 
-impl DisbotterFlow {
-    pub fn get_node(&self, uid: &str) -> &DisbotterFlowNode {
-        self.nodes.iter().find(|n| n.uid == uid).unwrap()
+    #[test]
+    fn package_nodes_get_valid_variable_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = dir.path().join("my-pkg");
+        std::fs::create_dir(&package).unwrap();
+        std::fs::write(package.join("disbotter-package.toml"), "name = \"my-pkg\"\nversion = \"1.0.0\"\nauthor = \"Test\"\n").unwrap();
+        std::fs::write(package.join("greet.rhai"), "const id = \"greet\";\nconst title = \"Greet\";\nconst description = \"\";\nconst category = \"Test\";\n\
+            const inputs = #{};\nconst outputs = #{ \"text-out\": #{ name: \"Text\", type: \"text\" } };\n\
+            fn action(builder) { builder.set_output(\"text-out\", `\"hi\"`); }\n").unwrap();
+
+        let code = compile(dir.path(), serde_json::json!([{ "uid": "n1", "type": "my-pkg/greet", "inputHardcoded": {} }]), serde_json::json!([
+            { "type": 0, "from": "start", "fromKey": "__flow_out__", "to": "n1", "toKey": "__flow_in__" },
+        ]));
+
+        let declaration = code.lines().find(|line| line.contains("= \"hi\";")).unwrap();
+        let name = declaration.trim().strip_prefix("const ").unwrap().split([':', ' ']).next().unwrap();
+        assert!(regex::Regex::new("^[A-Za-z_$][A-Za-z0-9_$]*$").unwrap().is_match(name), "{}", name);
+        assert_eq!(name, "__io_Nmy_pkg_greet_Otext_out_0");
     }
-}
 
-#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
-pub struct DisbotterFlowNode {
-    uid: String,
-    #[serde(rename = "type")]
-    pub(crate) node_type: String,
-    #[serde(rename = "inputHardcoded")]
-    input_hardcoded: HashMap<String, serde_json::Value>
-}
+    #[test]
+    fn input_with_many_connections_uses_the_first_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let value = "const pure;\nconst inputs = #{};\nconst outputs = #{ value: #{ name: \"Value\", type: \"text\" } };";
+        write_node(dir.path(), "first", value, "out value = `\"first\"`;");
+        write_node(dir.path(), "second", value, "out value = `\"second\"`;");
+        write_node(dir.path(), "print", "const inputs = #{ text: #{ name: \"Text\", type: \"text\" } };\nconst outputs = #{};", "let text = inv text; -> `console.log(${text});`;");
+
+        let code = compile(dir.path(), serde_json::json!([
+            { "uid": "a", "type": "first", "inputHardcoded": {} },
+            { "uid": "b", "type": "second", "inputHardcoded": {} },
+            { "uid": "p", "type": "print", "inputHardcoded": {} },
+        ]), serde_json::json!([
+            { "type": 0, "from": "start", "fromKey": "__flow_out__", "to": "p", "toKey": "__flow_in__" },
+            { "type": 1, "from": "a", "fromKey": "value", "to": "p", "toKey": "text" },
+            { "type": 1, "from": "b", "fromKey": "value", "to": "p", "toKey": "text" },
+        ]));
+
+        assert_eq!(code.matches("\"first\"").count(), 1);
+        assert!(!code.contains("\"second\""), "{}", code);
+        assert!(code.contains("console.log(__io_Nfirst_Ovalue_0);"), "{}", code);
+    }
 
-impl DisbotterFlowNode {
-    pub fn get_port_in(&self, key: &str) -> PortIdentifier {
-        PortIdentifier::Input {
-            node_uid: self.uid.clone(),
-            port_key: key.to_string()
+    #[test]
+    fn commands_compiled_in_parallel_keep_their_order() {
+        let dir = tempfile::tempdir().unwrap();
+        write_script(dir.path(), START_NODE, "");
+        write_node(dir.path(), "value", "const pure;\nconst inputs = #{};\nconst outputs = #{ value: #{ name: \"Value\", type: \"number\" } };", "out value = `${builder.get_random_var_name()}`;");
+        write_node(dir.path(), "print", "const inputs = #{ value: #{ name: \"Value\", type: \"number\" } };\nconst outputs = #{};", "let value = inv value; -> `console.log(${value});`;");
+
+        let commands = (0..16).map(|i| serde_json::json!({
+            "uid": format!("c{}", i), "name": format!("cmd{}", i), "description": "", "options": [],
+            "flow": {
+                "nodes": [
+                    { "uid": "start", "type": START_NODE, "inputHardcoded": {} },
+                    { "uid": "v", "type": "value", "inputHardcoded": {} },
+                    { "uid": "p", "type": "print", "inputHardcoded": {} },
+                ],
+                "connections": [
+                    { "type": 0, "from": "start", "fromKey": "__flow_out__", "to": "p", "toKey": "__flow_in__" },
+                    { "type": 1, "from": "v", "fromKey": "value", "to": "p", "toKey": "value" },
+                ],
+            },
+        })).collect::<Vec<serde_json::Value>>();
+        let project: DisbotterProjectData = serde_json::from_value(serde_json::json!({
+            "metadata": { "name": "test", "formatVersion": 2 },
+            "content": { "commands": commands },
+        })).unwrap();
+        let registry = Arc::new(NodeRegistry::load(&[dir.path().to_path_buf()], &shared_engine()).unwrap());
+
+        let compile = || {
+            let mut compiler = NodesJSCompiler::new(project.clone());
+            compiler.set_registry(Arc::clone(&registry));
+            let program = compiler.compile_project().unwrap();
+            program.files.iter()
+                .map(|file| {
+                    let file = file.lock().unwrap();
+                    (file.path.clone(), file.code.clone())
+                })
+                .collect::<Vec<(String, String)>>()
+        };
+        let files = compile();
+
+        let paths = files.iter().map(|(path, _)| path.as_str()).collect::<Vec<&str>>();
+        let mut expected = (0..16).map(|i| format!("commands/cmd{}.ts", i)).collect::<Vec<String>>();
+        expected.push(REGISTRY_PATH.to_string());
+        assert_eq!(paths, expected);
+        // Every command has its own variable counter, so the code doesn't depend on the other commands
+        for (_, code) in files.iter().take(16) {
+            assert!(code.contains("const __io_Nvalue_Ovalue_1: number = 0;"), "{}", code);
         }
+        assert_eq!(files, compile());
     }
 
-    pub fn get_port_out(&self, key: &str) -> PortIdentifier {
-        PortIdentifier::Output {
-            node_uid: self.uid.clone(),
-            port_key: key.to_string()
+    #[test]
+    fn registry_lists_compiled_commands() {
+        let project = serde_json::from_value(serde_json::json!({
+            "metadata": { "name": "test", "formatVersion": 2 },
+            "content": { "commands": [] },
+        })).unwrap();
+        let mut compiler = NodesJSCompiler::new(project);
+        for (name, description) in [("ping", "Replies with \"pong\""), ("greet", "")] {
+            compiler.program.register(RegistryEntry {
+                kind: RegistryEntryKind::Command,
+                name: name.to_string(),
+                description: description.to_string(),
+                path: format!("commands/{}.ts", name),
+            });
         }
+        compiler.compile_registry();
+
+        let file = compiler.program.files[0].lock().unwrap();
+        assert_eq!(file.path, REGISTRY_PATH);
+        assert_eq!(file.code, [
+            "import type { Command } from \"disbotter\";",
+            "import __commands_0 from \"./commands/ping\";",
+            "import __commands_1 from \"./commands/greet\";",
+            "",
+            "export interface RegistryEntry<T> {",
+            "    name: string;",
+            "    description: string;",
+            "    file: string;",
+            "    handler: T;",
+            "}",
+            "",
+            "export const commands: RegistryEntry<typeof Command>[] = [",
+            "    { name: \"ping\", description: \"Replies with \\\"pong\\\"\", file: \"commands/ping.ts\", handler: __commands_0 },",
+            "    { name: \"greet\", description: \"\", file: \"commands/greet.ts\", handler: __commands_1 },",
+            "];",
+            "",
+            "export default { commands };",
+            "",
+        ].join("\n"));
     }
-}
 
-#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
-pub struct DisbotterFlowConnection {
-    #[serde(rename = "type")]
-    connection_type: i32,
-    from: String,
-    to: String,
-    #[serde(rename = "fromKey")]
-    from_key: String,
-    #[serde(rename = "toKey")]
-    to_key: String
-}
\ No newline at end of file
+    #[test]
+    fn cache_key_covers_used_scripts_and_command() {
+        let dir = tempfile::tempdir().unwrap();
+        write_script(dir.path(), "used", "-> `a();`;");
+        write_script(dir.path(), "unused", "-> `b();`;");
+        let key = cache_key(dir.path(), "hello");
+        assert_eq!(key, cache_key(dir.path(), "hello"));
+
+        // Scripts the command doesn't use don't invalidate it
+        write_script(dir.path(), "unused", "-> `c();`;");
+        assert_eq!(key, cache_key(dir.path(), "hello"));
+
+        write_script(dir.path(), "used", "-> `c();`;");
+        let changed_script = cache_key(dir.path(), "hello");
+        assert_ne!(key, changed_script);
+
+        assert_ne!(changed_script, cache_key(dir.path(), "bye"));
+    }
+}
@@ -0,0 +1,364 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+
+use colored::*;
+
+use crate::market::IndexSource;
+
+/// Name of the configuration file, it is looked up in the working directory and its parents
+pub const CONFIG_FILE: &str = "disbotter.toml";
+
+/// Default address of the REST API server
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+pub const DEFAULT_PORT: u16 = 3000;
+
+/// Contents of `disbotter.toml`.
+/// Relative paths in the file are relative to the directory the file is in
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub build: BuildConfig,
+    pub server: ServerConfig,
+    pub packages: PackagesConfig,
+    /// Named sets of build settings, selected with `--profile`, that override `build`
+    pub profiles: BTreeMap<String, BuildConfig>,
+}
+
+/// Settings used by commands that load and compile projects
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BuildConfig {
+    /// Directories containing the nodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nodes: Option<Vec<PathBuf>>,
+    /// Project (.dbp) file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project: Option<PathBuf>,
+    /// Directory the project is compiled to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub force: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_cache: Option<bool>,
+}
+
+/// Settings of the REST API server
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Directories containing the nodes, `build.nodes` are used if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nodes: Option<Vec<PathBuf>>,
+    /// Directory the projects are run in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub projects: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+}
+
+/// Settings of the `nodes` commands that install node packages
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PackagesConfig {
+    /// Package index, a directory or an HTTP(S) URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<String>,
+    /// Directory the packages are installed to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+}
+
+/// Configuration after merging the file, selected profile and command line flags
+#[derive(Debug, Default, Clone, serde::Serialize)]
+pub struct ResolvedConfig {
+    /// Configuration file that was used, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub build: BuildConfig,
+    pub server: ServerConfig,
+    pub packages: PackagesConfig,
+}
+
+/// All possible errors that can occur while loading the configuration
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Invalid(PathBuf, toml::de::Error),
+    UnknownProfile(String),
+}
+
+impl ConfigError {
+    pub fn to_pretty(&self) -> String {
+        match self {
+            ConfigError::Io(path, err) => {
+                format!("{}: {} ({})", "IO error".red(), err, path.display())
+            },
+            ConfigError::Invalid(path, err) => {
+                format!("{} ({}): {}", "Invalid configuration".red(), path.display(), err)
+            },
+            ConfigError::UnknownProfile(profile) => {
+                format!("{}: {}", "Unknown profile".red(), profile)
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_pretty())
+    }
+}
+
+impl BuildConfig {
+    /// Returns these settings overridden by all settings that are set in `other`
+    pub fn merge(self, other: BuildConfig) -> BuildConfig {
+        BuildConfig {
+            nodes: other.nodes.or(self.nodes),
+            project: other.project.or(self.project),
+            output: other.output.or(self.output),
+            force: other.force.or(self.force),
+            no_cache: other.no_cache.or(self.no_cache),
+        }
+    }
+
+    fn relative_to(self, dir: &Path) -> BuildConfig {
+        BuildConfig {
+            nodes: self.nodes.map(|nodes| nodes.iter().map(|path| dir.join(path)).collect()),
+            project: self.project.map(|path| dir.join(path)),
+            output: self.output.map(|path| dir.join(path)),
+            ..self
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Returns these settings overridden by all settings that are set in `other`
+    pub fn merge(self, other: ServerConfig) -> ServerConfig {
+        ServerConfig {
+            nodes: other.nodes.or(self.nodes),
+            projects: other.projects.or(self.projects),
+            host: other.host.or(self.host),
+            port: other.port.or(self.port),
+        }
+    }
+
+    fn relative_to(self, dir: &Path) -> ServerConfig {
+        ServerConfig {
+            nodes: self.nodes.map(|nodes| nodes.iter().map(|path| dir.join(path)).collect()),
+            projects: self.projects.map(|path| dir.join(path)),
+            ..self
+        }
+    }
+}
+
+impl PackagesConfig {
+    /// Returns these settings overridden by all settings that are set in `other`
+    pub fn merge(self, other: PackagesConfig) -> PackagesConfig {
+        PackagesConfig {
+            index: other.index.or(self.index),
+            dir: other.dir.or(self.dir),
+        }
+    }
+
+    fn relative_to(self, dir: &Path) -> PackagesConfig {
+        PackagesConfig {
+            // URLs are kept as they are
+            index: self.index.map(|index| match IndexSource::parse(&index) {
+                IndexSource::Dir(path) => dir.join(path).display().to_string(),
+                IndexSource::Http(url) => url,
+            }),
+            dir: self.dir.map(|path| dir.join(path)),
+        }
+    }
+}
+
+impl ResolvedConfig {
+    /// Returns this configuration overridden by all settings that are set on the command line
+    pub fn merge(self, build: BuildConfig, server: ServerConfig, packages: PackagesConfig) -> ResolvedConfig {
+        ResolvedConfig {
+            build: self.build.merge(build),
+            server: self.server.merge(server),
+            packages: self.packages.merge(packages),
+            ..self
+        }
+    }
+}
+
+impl Config {
+    /// Reads configuration from the given file
+    pub fn read(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        toml::from_str(&content).map_err(|err| ConfigError::Invalid(path.to_path_buf(), err))
+    }
+
+    /// Finds configuration file in the given directory or the closest parent that has one
+    pub fn discover(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .find(|path| path.is_file())
+    }
+
+    /// Loads configuration from the given file, or from the discovered one if no file is given,
+    /// and applies the selected profile. Without any configuration file, everything is left unset
+    pub fn load(file: Option<PathBuf>, profile: Option<String>) -> Result<ResolvedConfig, ConfigError> {
+        let file = match file {
+            Some(file) => Some(file),
+            None => std::env::current_dir().ok().and_then(|dir| Self::discover(&dir)),
+        };
+
+        let config = match &file {
+            Some(file) => Self::read(file)?,
+            None => Config::default(),
+        };
+        let dir = file.as_ref()
+            .and_then(|file| file.parent())
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        let mut build = config.build;
+        if let Some(name) = &profile {
+            let profile = config.profiles.get(name).ok_or_else(|| ConfigError::UnknownProfile(name.clone()))?;
+            build = build.merge(profile.clone());
+        }
+
+        Ok(ResolvedConfig {
+            file,
+            profile,
+            build: build.relative_to(&dir),
+            server: config.server.relative_to(&dir),
+            packages: config.packages.relative_to(&dir),
+        })
+    }
+}
+
+/// Splits comma separated paths given on the command line
+pub fn split_paths(paths: &str) -> Vec<PathBuf> {
+    paths.split(",").map(PathBuf::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_prefers_set_values() {
+        let base = BuildConfig {
+            nodes: Some(vec!["nodes".into()]),
+            project: Some("bot.dbp".into()),
+            force: Some(false),
+            ..Default::default()
+        };
+        let other = BuildConfig {
+            project: Some("other.dbp".into()),
+            output: Some("out".into()),
+            force: Some(true),
+            ..Default::default()
+        };
+
+        let merged = base.merge(other);
+        assert_eq!(merged.nodes, Some(vec![PathBuf::from("nodes")]));
+        assert_eq!(merged.project, Some(PathBuf::from("other.dbp")));
+        assert_eq!(merged.output, Some(PathBuf::from("out")));
+        assert_eq!(merged.force, Some(true));
+        assert_eq!(merged.no_cache, None);
+
+        let server = ServerConfig { port: Some(80), host: Some("0.0.0.0".into()), ..Default::default() }
+            .merge(ServerConfig { port: Some(8080), ..Default::default() });
+        assert_eq!(server.port, Some(8080));
+        assert_eq!(server.host.as_deref(), Some("0.0.0.0"));
+
+        let packages = PackagesConfig { index: Some("index".into()), dir: None }
+            .merge(PackagesConfig { index: None, dir: Some("packages".into()) });
+        assert_eq!(packages.index.as_deref(), Some("index"));
+        assert_eq!(packages.dir, Some(PathBuf::from("packages")));
+    }
+
+    #[test]
+    fn discover_finds_closest_file() {
+        let root = tempfile::tempdir().unwrap();
+        let nested = root.path().join("a").join("b");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(root.path().join(CONFIG_FILE), "").unwrap();
+
+        assert_eq!(Config::discover(&nested), Some(root.path().join(CONFIG_FILE)));
+
+        std::fs::write(nested.join(CONFIG_FILE), "").unwrap();
+        assert_eq!(Config::discover(&nested), Some(nested.join(CONFIG_FILE)));
+    }
+
+    #[test]
+    fn load_applies_profile_and_resolves_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join(CONFIG_FILE);
+        std::fs::write(&file, r#"
+            [build]
+            nodes = ["nodes"]
+            project = "bot.dbp"
+            output = "out"
+
+            [server]
+            port = 8080
+
+            [packages]
+            index = "index"
+            dir = "packages"
+
+            [profiles.release]
+            output = "dist"
+            force = true
+        "#).unwrap();
+
+        let config = Config::load(Some(file.clone()), None).unwrap();
+        assert_eq!(config.file.as_ref(), Some(&file));
+        assert_eq!(config.build.nodes, Some(vec![root.path().join("nodes")]));
+        assert_eq!(config.build.output, Some(root.path().join("out")));
+        assert_eq!(config.build.force, None);
+        assert_eq!(config.server.port, Some(8080));
+        assert_eq!(config.packages.index, Some(root.path().join("index").display().to_string()));
+        assert_eq!(config.packages.dir, Some(root.path().join("packages")));
+
+        let release = Config::load(Some(file.clone()), Some("release".into())).unwrap();
+        assert_eq!(release.build.project, Some(root.path().join("bot.dbp")));
+        assert_eq!(release.build.output, Some(root.path().join("dist")));
+        assert_eq!(release.build.force, Some(true));
+
+        assert!(matches!(Config::load(Some(file), Some("debug".into())), Err(ConfigError::UnknownProfile(name)) if name == "debug"));
+    }
+
+    #[test]
+    fn flags_override_loaded_configuration() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join(CONFIG_FILE);
+        std::fs::write(&file, "[build]\noutput = \"out\"\nproject = \"bot.dbp\"\n\n[server]\nport = 8080\n\n[packages]\ndir = \"packages\"\n").unwrap();
+
+        let config = Config::load(Some(file.clone()), None).unwrap().merge(
+            BuildConfig { output: Some("dist".into()), force: Some(true), ..Default::default() },
+            ServerConfig { host: Some("0.0.0.0".into()), ..Default::default() },
+            PackagesConfig { index: Some("https://example.com".into()), dir: None },
+        );
+        assert_eq!(config.file, Some(file));
+        assert_eq!(config.build.output, Some(PathBuf::from("dist")));
+        assert_eq!(config.build.project, Some(root.path().join("bot.dbp")));
+        assert_eq!(config.build.force, Some(true));
+        assert_eq!((config.server.host.as_deref(), config.server.port), (Some("0.0.0.0"), Some(8080)));
+        assert_eq!(config.packages.index.as_deref(), Some("https://example.com"));
+        assert_eq!(config.packages.dir, Some(root.path().join("packages")));
+    }
+
+    #[test]
+    fn keeps_index_urls() {
+        let config = PackagesConfig { index: Some("https://example.com/index".into()), dir: None }.relative_to(Path::new("/project"));
+        assert_eq!(config.index.as_deref(), Some("https://example.com/index"));
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join(CONFIG_FILE);
+        std::fs::write(&file, "[build]\noutptu = \"out\"\n").unwrap();
+        assert!(matches!(Config::read(&file), Err(ConfigError::Invalid(..))));
+    }
+}
@@ -0,0 +1,246 @@
+use std::{collections::{BTreeMap, HashMap}, fmt::Write, fs, path::{Path, PathBuf}};
+
+use crate::{loader::{InputConstraints, KeyMap, NodeIO, NodeIOTy}, registry::{NodeRegistry, RegisteredNode}};
+
+/// Name of the generated index page
+pub const INDEX_PAGE: &str = "README.md";
+
+/// Nodes shown on one page, builtin nodes are grouped by their category and nodes of packages by the package too
+struct Group<'a> {
+    title: String,
+    file: String,
+    nodes: Vec<&'a RegisteredNode>,
+}
+
+/// Writes Markdown reference of all nodes to the directory, one page per category and an index linking them.
+/// Returns the written files
+pub fn export_node_docs(nodes: &NodeRegistry, target_dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(target_dir)?;
+
+    let mut groups: BTreeMap<(Option<&str>, &str), Group> = BTreeMap::new();
+    for node in nodes.iter() {
+        let metadata = node.metadata.as_ref();
+        let package = metadata.package.as_ref().map(|package| package.name.as_str());
+        let group = groups.entry((package, metadata.category.as_str())).or_insert_with(|| Group {
+            title: match package {
+                Some(package) => format!("{} ({})", metadata.category, package),
+                None => metadata.category.clone(),
+            },
+            file: match package {
+                Some(package) => format!("{}-{}.md", slug(package), slug(&metadata.category)),
+                None => format!("{}.md", slug(&metadata.category)),
+            },
+            nodes: vec![],
+        });
+        group.nodes.push(node);
+    }
+
+    // Pages of the nodes by their id, used to link replacements of deprecated nodes
+    let pages = groups.values()
+        .flat_map(|group| group.nodes.iter().map(|node| (node.id().to_string(), group.file.clone())))
+        .collect::<HashMap<String, String>>();
+
+    let mut written = vec![];
+    let mut index = String::new();
+    writeln!(index, "# Node reference\n").unwrap();
+    writeln!(index, "Generated by `disbotter gen-node-docs` from the node scripts, do not edit it by hand.\n").unwrap();
+    writeln!(index, "| Category | Nodes |\n| --- | --- |").unwrap();
+
+    for group in groups.values_mut() {
+        group.nodes.sort_by(|a, b| a.metadata.title.cmp(&b.metadata.title));
+        writeln!(index, "| [{}]({}) | {} |", escape(&group.title), group.file, group.nodes.len()).unwrap();
+
+        let path = target_dir.join(&group.file);
+        fs::write(&path, category_page(group, &pages))?;
+        written.push(path);
+    }
+
+    let path = target_dir.join(INDEX_PAGE);
+    fs::write(&path, index)?;
+    written.push(path);
+    Ok(written)
+}
+
+fn category_page(group: &Group, pages: &HashMap<String, String>) -> String {
+    let mut page = String::new();
+    writeln!(page, "# {}\n", group.title).unwrap();
+    writeln!(page, "[All categories]({})\n", INDEX_PAGE).unwrap();
+    for node in group.nodes.iter() {
+        writeln!(page, "- [{}](#{})", node.metadata.title, node.id()).unwrap();
+    }
+
+    for node in group.nodes.iter() {
+        let metadata = node.metadata.as_ref();
+        // Titles aren't unique, so the links use ids of the nodes
+        writeln!(page, "\n<a id=\"{}\"></a>\n\n## {}\n", metadata.id, metadata.title).unwrap();
+        writeln!(page, "{}\n", metadata.description).unwrap();
+        writeln!(page, "- Id: `{}`", metadata.id).unwrap();
+        if let Some(package) = &metadata.package {
+            writeln!(page, "- Package: `{}` {} by {}", package.name, package.version, package.author).unwrap();
+        }
+        if let Some(deprecation) = &metadata.deprecated {
+            match &deprecation.replacement {
+                Some(replacement) => match pages.get(replacement) {
+                    Some(file) => writeln!(page, "- **Deprecated**: use [`{}`]({}#{}) instead", replacement, file, replacement).unwrap(),
+                    None => writeln!(page, "- **Deprecated**: use `{}` instead", replacement).unwrap(),
+                },
+                None => writeln!(page, "- **Deprecated**").unwrap(),
+            }
+        }
+        if metadata.experimental {
+            writeln!(page, "- **Experimental**: the node may still change or be removed").unwrap();
+        }
+        if !metadata.keywords.is_empty() {
+            writeln!(page, "- Keywords: {}", metadata.keywords.join(", ")).unwrap();
+        }
+        if node.is_pure {
+            writeln!(page, "- Pure: has no flow ports, it is computed where its outputs are used").unwrap();
+        }
+
+        if !metadata.inputs.keys.is_empty() {
+            writeln!(page, "\n**Inputs**\n\n| Key | Name | Type | Default | Constraints |\n| --- | --- | --- | --- | --- |").unwrap();
+            for (key, port) in ports(&metadata.inputs) {
+                let default = metadata.default_hardcoded.get(key)
+                    .map(|value| format!("`{:?}`", value))
+                    .unwrap_or_default();
+                // Variadic inputs are shown the same way they are declared in scripts
+                let key = if port.variadic { format!("{}[]", key) } else { key.clone() };
+                writeln!(page, "| `{}` | {} | {} | {} | {} |", key, escape(&port.name), describe_type(&port.ty), escape(&default), describe_constraints(&port.constraints)).unwrap();
+            }
+        }
+        if !metadata.outputs.keys.is_empty() {
+            writeln!(page, "\n**Outputs**\n\n| Key | Name | Type |\n| --- | --- | --- |").unwrap();
+            for (key, port) in ports(&metadata.outputs) {
+                writeln!(page, "| `{}` | {} | {} |", key, escape(&port.name), describe_type(&port.ty)).unwrap();
+            }
+        }
+        if let Some(docs) = &metadata.docs {
+            writeln!(page, "\n{}", demote_headings(docs)).unwrap();
+        }
+    }
+    page
+}
+
+fn ports(ports: &KeyMap<String, NodeIO>) -> impl Iterator<Item = (&String, &NodeIO)> {
+    ports.keys.iter().filter_map(|key| Some((key, ports.values.get(key)?)))
+}
+
+fn describe_type(ty: &NodeIOTy) -> String {
+    // Ports with a type variable have the same type as the other ports with it
+    let mut description = match &ty.generic {
+        Some(generic) => format!("{} (type variable)", generic),
+        None => ty.ty.name().to_string(),
+    };
+    if !ty.struct_tags.is_empty() {
+        description += &format!(" [{}]", ty.struct_tags.join(", "));
+    }
+    if let Some(ts_type) = &ty.ts_type {
+        description += &format!(" (`{}`)", ts_type);
+    }
+    escape(&description)
+}
+
+fn describe_constraints(constraints: &InputConstraints) -> String {
+    let mut description = vec![];
+    if constraints.required {
+        description.push("required".to_string());
+    }
+    if let Some(min) = constraints.min {
+        description.push(format!("min {}", min));
+    }
+    if let Some(max) = constraints.max {
+        description.push(format!("max {}", max));
+    }
+    if let Some(max_length) = constraints.max_length {
+        description.push(format!("at most {} characters", max_length));
+    }
+    if let Some(pattern) = &constraints.pattern {
+        description.push(format!("matches `{}`", pattern));
+    }
+    if !constraints.choices.is_empty() {
+        let choices = constraints.choices.iter().map(|choice| format!("`{}`", choice)).collect::<Vec<String>>();
+        description.push(format!("one of {}", choices.join(", ")));
+    }
+    if constraints.multiline {
+        description.push("multiline".to_string());
+    }
+    escape(&description.join(", "))
+}
+
+/// Makes headings of the script documentation smaller than the heading of the node,
+/// so `# Example` in a script doesn't look like a new category
+fn demote_headings(docs: &str) -> String {
+    let mut in_code = false;
+    docs.lines()
+        .map(|line| {
+            if line.trim_start().starts_with("```") {
+                in_code = !in_code;
+            }
+            let level = line.chars().take_while(|c| *c == '#').count();
+            if level > 0 && !in_code {
+                format!("{}{}", "#".repeat((level + 2).min(6)), &line[level..])
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Escapes text used in a table cell
+fn escape(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+/// File name friendly form of a category or package name
+fn slug(name: &str) -> String {
+    let slug = name.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-");
+    if slug.is_empty() { "other".to_string() } else { slug }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::shared_engine;
+
+    use super::*;
+
+    fn write_script(dir: &Path, id: &str, category: &str, constants: &str) {
+        std::fs::write(dir.join(format!("{}.rhai", id)), format!(
+            "const id = \"{}\";\nconst title = \"{}\";\nconst description = \"\";\nconst category = \"{}\";\n{}\n\
+            const inputs = #{{}};\nconst outputs = #{{}};\nfn action(builder) {{}}\n",
+            id, id, category, constants
+        )).unwrap();
+    }
+
+    #[test]
+    fn pages_show_presentation_metadata() {
+        let nodes = tempfile::tempdir().unwrap();
+        write_script(nodes.path(), "old_greet", "Text", "const deprecated = \"greet\";");
+        write_script(nodes.path(), "greet", "Chat Messages", "const experimental;\nconst keywords = [\"hello\", \"welcome\"];");
+        write_script(nodes.path(), "gone", "Text", "const deprecated;");
+        let registry = NodeRegistry::load(&[nodes.path().to_path_buf()], &shared_engine()).unwrap();
+
+        let target = tempfile::tempdir().unwrap();
+        let written = export_node_docs(&registry, target.path()).unwrap();
+        let names = written.iter().map(|path| path.file_name().unwrap().to_str().unwrap()).collect::<Vec<&str>>();
+        assert_eq!(names, ["chat-messages.md", "text.md", INDEX_PAGE]);
+
+        let text = std::fs::read_to_string(target.path().join("text.md")).unwrap();
+        // Replacement is linked on the page of its category
+        assert!(text.contains("- **Deprecated**: use [`greet`](chat-messages.md#greet) instead"), "{}", text);
+        assert!(text.contains("- **Deprecated**\n"), "{}", text);
+
+        let chat = std::fs::read_to_string(target.path().join("chat-messages.md")).unwrap();
+        assert!(chat.contains("- **Experimental**: the node may still change or be removed"), "{}", chat);
+        assert!(chat.contains("- Keywords: hello, welcome"), "{}", chat);
+        assert!(!chat.contains("Deprecated"), "{}", chat);
+
+        let index = std::fs::read_to_string(target.path().join(INDEX_PAGE)).unwrap();
+        assert!(index.contains("| [Chat Messages](chat-messages.md) | 1 |"), "{}", index);
+        assert!(index.contains("| [Text](text.md) | 2 |"), "{}", index);
+    }
+}
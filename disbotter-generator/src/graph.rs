@@ -0,0 +1,159 @@
+use std::{collections::{hash_map::Entry, HashMap}, sync::Arc};
+
+use crate::{compiler::{PortIdentifier, CompilerError}, project::{DisbotterFlow, DisbotterFlowNode, DisbotterFlowConnection}};
+
+/// Flow with indexes for constant time lookups of nodes and connections.
+/// It is built once before the flow is compiled and backs all lookups done by the compiler
+#[derive(Debug, Clone)]
+pub struct FlowGraph {
+    flow: Arc<DisbotterFlow>,
+    /// Node uid -> index in `flow.nodes`
+    nodes: HashMap<String, usize>,
+    /// Node type -> index of the first node of the type in `flow.nodes`
+    by_type: HashMap<String, usize>,
+    /// Node uid -> input key -> index in `flow.connections`
+    incoming: HashMap<String, HashMap<String, usize>>,
+    /// Node uid -> output key -> indexes in `flow.connections`
+    outgoing: HashMap<String, HashMap<String, Vec<usize>>>,
+    /// Node uid -> indexes of connections used by the inputs of the node, in the order they are in the flow
+    incoming_by_node: HashMap<String, Vec<usize>>,
+}
+
+impl FlowGraph {
+    /// Builds indexes for the flow, fails if any connection references node that doesn't exist
+    pub fn new(flow: Arc<DisbotterFlow>) -> Result<Self, CompilerError> {
+        let nodes = flow.nodes.iter()
+            .enumerate()
+            .map(|(i, node)| (node.uid.clone(), i))
+            .collect::<HashMap<String, usize>>();
+        let mut by_type = HashMap::new();
+        for (i, node) in flow.nodes.iter().enumerate() {
+            by_type.entry(node.node_type.clone()).or_insert(i);
+        }
+
+        let mut incoming: HashMap<String, HashMap<String, usize>> = HashMap::new();
+        let mut outgoing: HashMap<String, HashMap<String, Vec<usize>>> = HashMap::new();
+        let mut incoming_by_node: HashMap<String, Vec<usize>> = HashMap::new();
+
+        for (i, conn) in flow.connections.iter().enumerate() {
+            for uid in [&conn.from, &conn.to] {
+                if !nodes.contains_key(uid) {
+                    return Err(CompilerError::NodeUidNotFound(uid.clone()));
+                }
+            }
+
+            // Input can have only one connection, if there are more the first one wins
+            if let Entry::Vacant(entry) = incoming.entry(conn.to.clone()).or_default().entry(conn.to_key.clone()) {
+                entry.insert(i);
+                incoming_by_node.entry(conn.to.clone()).or_default().push(i);
+            }
+            outgoing.entry(conn.from.clone()).or_default()
+                .entry(conn.from_key.clone()).or_default()
+                .push(i);
+        }
+
+        Ok(Self {
+            flow,
+            nodes,
+            by_type,
+            incoming,
+            outgoing,
+            incoming_by_node,
+        })
+    }
+
+    /// Gets node with the given uid
+    pub fn get_node(&self, uid: &str) -> Result<&DisbotterFlowNode, CompilerError> {
+        self.nodes.get(uid)
+            .map(|i| &self.flow.nodes[*i])
+            .ok_or_else(|| CompilerError::NodeUidNotFound(uid.to_string()))
+    }
+
+    /// Gets first node of the given type
+    pub fn find_node_of_type(&self, node_type: &str) -> Option<&DisbotterFlowNode> {
+        self.by_type.get(node_type).map(|i| &self.flow.nodes[*i])
+    }
+
+    /// Gets whatever port is connected to.
+    /// For outputs connected to multiple inputs, the first connection is returned
+    pub fn get_target(&self, port: &PortIdentifier) -> Option<PortIdentifier> {
+        match port {
+            PortIdentifier::Input { node_uid, port_key } => {
+                let conn = self.get_incoming(node_uid, port_key)?;
+                Some(PortIdentifier::Output { node_uid: conn.from.clone(), port_key: conn.from_key.clone() })
+            },
+            PortIdentifier::Output { node_uid, port_key } => {
+                let i = *self.outgoing.get(node_uid)?.get(port_key)?.first()?;
+                let conn = &self.flow.connections[i];
+                Some(PortIdentifier::Input { node_uid: conn.to.clone(), port_key: conn.to_key.clone() })
+            },
+            _ => None
+        }
+    }
+
+    /// Gets connection going into the given input
+    pub fn get_incoming(&self, node_uid: &str, port_key: &str) -> Option<&DisbotterFlowConnection> {
+        let i = self.incoming.get(node_uid)?.get(port_key)?;
+        Some(&self.flow.connections[*i])
+    }
+
+    /// Gets connections used by the inputs of the node, in the order they are in the flow.
+    /// Every input has at most one, the same one as `get_incoming` returns
+    pub fn get_node_inputs(&self, node_uid: &str) -> Vec<&DisbotterFlowConnection> {
+        self.incoming_by_node.get(node_uid)
+            .map(|conns| conns.iter().map(|i| &self.flow.connections[*i]).collect())
+            .unwrap_or_default()
+    }
+
+    /// Gets the flow this graph was built from
+    pub fn flow(&self) -> &DisbotterFlow {
+        &self.flow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> FlowGraph {
+        let flow = serde_json::from_value(serde_json::json!({
+            "nodes": [
+                { "uid": "start", "type": "__start__", "inputHardcoded": {} },
+                { "uid": "a", "type": "print", "inputHardcoded": {} },
+                { "uid": "b", "type": "print", "inputHardcoded": {} },
+            ],
+            "connections": [
+                { "type": 0, "from": "start", "fromKey": "__flow_out__", "to": "a", "toKey": "__flow_in__" },
+                { "type": 2, "from": "a", "fromKey": "text", "to": "b", "toKey": "text" },
+                { "type": 2, "from": "start", "fromKey": "text", "to": "b", "toKey": "text" },
+            ],
+        })).unwrap();
+        FlowGraph::new(Arc::new(flow)).unwrap()
+    }
+
+    #[test]
+    fn finds_first_node_of_type() {
+        let graph = graph();
+        assert_eq!(graph.find_node_of_type("print").unwrap().uid, "a");
+        assert!(graph.find_node_of_type("missing").is_none());
+    }
+
+    #[test]
+    fn first_connection_of_input_wins() {
+        let graph = graph();
+        let target = graph.get_target(&PortIdentifier::Input { node_uid: "b".to_string(), port_key: "text".to_string() });
+        assert_eq!(target, Some(PortIdentifier::Output { node_uid: "a".to_string(), port_key: "text".to_string() }));
+        let inputs = graph.get_node_inputs("b");
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0].from, "a");
+    }
+
+    #[test]
+    fn unknown_node_in_connection() {
+        let flow = serde_json::from_value(serde_json::json!({
+            "nodes": [],
+            "connections": [{ "type": 0, "from": "a", "fromKey": "x", "to": "b", "toKey": "y" }],
+        })).unwrap();
+        assert!(matches!(FlowGraph::new(Arc::new(flow)), Err(CompilerError::NodeUidNotFound(uid)) if uid == "a"));
+    }
+}
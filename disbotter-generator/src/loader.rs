@@ -1,186 +1,544 @@
-use std::{path::PathBuf, collections::{HashMap, BTreeMap}, fs::{self, File}, hash::Hash, fmt::{Debug, Formatter}};
+use std::{path::{Path, PathBuf}, collections::HashMap, fs::{self, File}, hash::Hash, fmt::{Debug, Formatter}};
 
-use rhai::{Engine, Dynamic, EvalAltResult};
+use rhai::{Dynamic, EvalAltResult, Stmt, Expr};
+use regex::Regex;
 use serde::ser::SerializeMap;
+use colored::*;
 
-use crate::compiler::upgrade_engine;
+use crate::{package::Package, registry::NodeRegistry};
 
-/// This struct is responsible for loading node metadata from a script file
-/// and converting it to a .json file that can be used by the web editor
-pub struct NodeScriptLoader {
-    /// RHAI engine used to load the script
-    pub engine: Engine,
-}
+/// Fields allowed in input declarations
+const INPUT_FIELDS: &[&str] = &[
+    "name", "type", "struct_tags", "ts_type", "ts_import", "index", "start_value",
+    "min", "max", "max_length", "pattern", "choices", "multiline", "required",
+];
+/// Fields allowed in output declarations
+const OUTPUT_FIELDS: &[&str] = &["name", "type", "struct_tags", "ts_type", "ts_import", "index"];
+/// Constants a script can declare, any other top level constant is most likely a typo
+const METADATA_CONSTANTS: &[&str] = &[
+    "id", "title", "description", "category", "color", "icon", "keywords", "experimental", "deprecated",
+    "pure", "noFlowIn", "noFlowOut", "overrides", "inputs", "outputs", "variableInput", "lenientTypes",
+];
+/// Types of inputs, flow input is added to every node that isn't pure
+const INPUT_TYPES: &[&str] = &["text", "number", "boolean", "struct", "any"];
+const OUTPUT_TYPES: &[&str] = &["flow", "text", "number", "boolean", "struct", "any"];
 
 /// All possible errors that can occur while loading a node script
 #[derive(Debug)]
 pub enum NodeScriptLoadingError {
+    Io(PathBuf, std::io::Error),
     InvalidScript(PathBuf, Box<EvalAltResult>),
-    MissingValue(String),
-    InvalidIODeclaration(PathBuf, &'static str),
+    /// Constants declaring the node are missing or invalid, line is known if the problem is in a declared value
+    InvalidMetadata { script: PathBuf, line: Option<usize>, message: String },
+    /// Two scripts declare node with the same id, contains paths of the first and the second script
+    DuplicateId { id: String, first: PathBuf, second: PathBuf },
+    InvalidManifest(PathBuf, String),
+    /// Package requires newer version of the generator
+    IncompatiblePackage { manifest: PathBuf, name: String, required: semver::Version },
+    InvalidLockfile(PathBuf, String),
+    /// File of an installed package doesn't match the lockfile
+    LockMismatch { file: PathBuf, lockfile: PathBuf, reason: String },
 }
 
-impl NodeScriptLoader {
-    /// Creates a new NodeScriptLoader
-    pub fn new() -> Self {
-        let mut engine = Engine::new();
-        upgrade_engine(&mut engine);
+impl NodeScriptLoadingError {
+    pub fn to_pretty(&self) -> String {
+        match self {
+            NodeScriptLoadingError::Io(path, err) => {
+                format!("{}: {} ({})", "IO error".red(), err, path.display())
+            },
+            NodeScriptLoadingError::InvalidScript(path, err) => {
+                format!("{}: {} ({})", "Invalid script".red(), err, path.display())
+            },
+            NodeScriptLoadingError::InvalidMetadata { script, line: Some(line), message } => {
+                format!("{}: {} ({}:{})", "Invalid node metadata".red(), message, script.display(), line)
+            },
+            NodeScriptLoadingError::InvalidMetadata { script, line: None, message } => {
+                format!("{}: {} ({})", "Invalid node metadata".red(), message, script.display())
+            },
+            NodeScriptLoadingError::DuplicateId { id, first, second } => {
+                format!(
+                    "{}: \"{}\" is declared in {} and {} (add `const overrides;` to the second script if it should replace the first one)",
+                    "Duplicate node id".red(), id, first.display(), second.display()
+                )
+            },
+            NodeScriptLoadingError::InvalidManifest(path, reason) => {
+                format!("{}: {} ({})", "Invalid package manifest".red(), reason, path.display())
+            },
+            NodeScriptLoadingError::IncompatiblePackage { manifest, name, required } => {
+                format!(
+                    "{}: package {} requires generator {} or newer, but this is {} ({})",
+                    "Incompatible package".red(), name, required, env!("CARGO_PKG_VERSION"), manifest.display()
+                )
+            },
+            NodeScriptLoadingError::InvalidLockfile(path, reason) => {
+                format!("{}: {} ({})", "Invalid lockfile".red(), reason, path.display())
+            },
+            NodeScriptLoadingError::LockMismatch { file, lockfile, reason } => {
+                format!(
+                    "{}: {} ({}), reinstall the package with `disbotter nodes add` to restore it ({})",
+                    "Package doesn't match the lockfile".red(), reason, file.display(), lockfile.display()
+                )
+            }
+        }
+    }
+}
+
+/// Problem in the metadata of a script.
+/// `key` is path to the value with the problem, like `["inputs", "text", "type"]`, it is used to find the line
+struct MetadataError {
+    key: Vec<String>,
+    message: String,
+}
+
+impl MetadataError {
+    fn new(key: &[&str], message: String) -> Self {
         Self {
-            engine
+            key: key.iter().map(|segment| segment.to_string()).collect(),
+            message,
         }
     }
+}
 
-    /// Gets a variable from a HashMap, returning an error if it doesn't exist
-    fn get_variable<'a, T: Clone + 'static>(variables: &'a HashMap<String, rhai::Dynamic>, name: &str) -> Result<T, NodeScriptLoadingError> {
-        if let Some(value) = variables.get(name) {
-            return Ok(value.clone_cast())
+/// Extracts node metadata from a compiled script and validates it.
+/// Metadata are the constants declared at the top of the script (`id`, `title`, `inputs`, ...).
+/// Optimization folds declared values into constants without positions,
+/// so lines of invalid values are looked up in `unoptimized` AST of the same script
+pub fn node_from_ast(ast: &rhai::AST, unoptimized: &rhai::AST, script: &Path) -> Result<Node, NodeScriptLoadingError> {
+    parse_metadata(ast).map_err(|err| NodeScriptLoadingError::InvalidMetadata {
+        script: script.to_path_buf(),
+        line: find_line(unoptimized, &err.key),
+        message: err.message,
+    })
+}
+
+fn parse_metadata(ast: &rhai::AST) -> Result<Node, MetadataError> {
+    // Get all constant variables
+    let mut variables = HashMap::new();
+    for (name, _, value) in ast.iter_literal_variables(true, false) {
+        if !METADATA_CONSTANTS.contains(&name) {
+            return Err(MetadataError::new(&[name], format!("unknown constant \"{}\", allowed constants are {}", name, METADATA_CONSTANTS.join(", "))));
         }
+        variables.insert(name.to_string(), value);
+    }
+    let constant = |name: &str| variables.get(name)
+        .ok_or_else(|| MetadataError::new(&[], format!("missing constant \"{}\"", name)));
+    let string = |name: &str| cast::<String>(constant(name)?, &[name], "a string");
+    let optional_string = |name: &str| variables.get(name)
+        .map(|value| cast::<String>(value, &[name], "a string"))
+        .transpose();
 
-        Err(NodeScriptLoadingError::MissingValue(name.to_string()))
+    let id = string("id")?;
+    if id.is_empty() {
+        return Err(MetadataError::new(&["id"], "id can't be empty".to_string()));
+    }
+    if id.contains('/') {
+        return Err(MetadataError::new(&["id"], "id can't contain \"/\", it separates package name from the node id".to_string()));
     }
 
-    /// Loads a node from a script file
-    pub fn load(&mut self, script: PathBuf) -> Result<Node, NodeScriptLoadingError> {
-        // Compile script into AST
-        let ast = self.engine.compile_file(script.clone());
+    // Create node based on variables
+    let mut node = Node {
+        id,
+        title: string("title")?,
+        description: string("description")?,
+        category: string("category")?,
+        color: optional_string("color")?,
+        icon: optional_string("icon")?,
+        keywords: match variables.get("keywords") {
+            Some(keywords) => cast::<rhai::Array>(keywords, &["keywords"], "an array of strings")?
+                .iter()
+                .map(|keyword| cast::<String>(keyword, &["keywords"], "an array of strings"))
+                .collect::<Result<Vec<String>, MetadataError>>()?,
+            None => vec![],
+        },
+        experimental: match variables.get("experimental") {
+            Some(value) if value.is_unit() => true,
+            Some(value) => cast::<bool>(value, &["experimental"], "a boolean or have no value")?,
+            None => false,
+        },
+        deprecated: match variables.get("deprecated") {
+            Some(value) if value.is_unit() => Some(Deprecation { replacement: None }),
+            Some(value) => Some(Deprecation { replacement: Some(cast::<String>(value, &["deprecated"], "id of the replacement node or have no value")?) }),
+            None => None,
+        },
+        inputs: KeyMap::new(),
+        outputs: KeyMap::new(),
+        default_hardcoded: HashMap::new(),
+        package: None,
+        docs: doc_comment(ast),
+        variable_input: optional_string("variableInput")?,
+        lenient_types: variables.contains_key("lenientTypes"),
+    };
 
-        if let Err(e) = ast {
-            return Err(NodeScriptLoadingError::InvalidScript(script, e));
+    if let Some(color) = &node.color {
+        if !is_hex_color(color) {
+            return Err(MetadataError::new(&["color"], format!("color \"{}\" should be a hex color like \"#5865f2\"", color)));
         }
+    }
+
+    // Add flow I/O
+    if !variables.contains_key("noFlowIn") && !variables.contains_key("pure") {
+        node.inputs.insert("__flow_in__".to_string(), NodeIO {
+            ty: NodeIOTy {
+                ty: DataType::Flow,
+                ..Default::default()
+            },
+            name: "flow_in".to_string(),
+            constraints: InputConstraints::default(),
+            variadic: false,
+        });
+    }
+    if !variables.contains_key("noFlowOut") && !variables.contains_key("pure") {
+        node.outputs.insert("__flow_out__".to_string(), NodeIO {
+            ty: NodeIOTy {
+                ty: DataType::Flow,
+                ..Default::default()
+            },
+            name: "flow_out".to_string(),
+            constraints: InputConstraints::default(),
+            variadic: false,
+        });
+    }
 
-        let ast = ast.unwrap();
+    // Add inputs
+    let inputs = cast::<rhai::Map>(constant("inputs")?, &["inputs"], "a map")?;
+    let mut defaults = HashMap::new();
+    parse_ports(&inputs, PortKind::Input, &mut node.inputs, |key, ty, port| {
+        let constraints = parse_constraints(key, ty, port)?;
+        if key.ends_with("[]") && port.contains_key("start_value") {
+            return Err(MetadataError::new(&["inputs", key, "start_value"], format!("variadic input \"{}\" can't have a start value", key)));
+        }
+        if let Some(default) = port.get("start_value") {
+            check_start_value(default, ty, key)?;
+            let value = serde_json::to_value(default).unwrap_or_default();
+            constraints.check(&value).map_err(|message| {
+                MetadataError::new(&["inputs", key, "start_value"], format!("start value of input \"{}\" is invalid: {}", key, message))
+            })?;
+            defaults.insert(key.to_string(), default.clone());
+        }
+        Ok(constraints)
+    })?;
+    node.default_hardcoded = defaults;
 
-        // Get all constant variables
-        let variables = ast.iter_literal_variables(true, false)
-            .map(|(name, _, value)| (name.to_string(), value.clone()))
-            .collect::<HashMap<String, rhai::Dynamic>>();
+    // Add outputs
+    let outputs = cast::<rhai::Map>(constant("outputs")?, &["outputs"], "a map")?;
+    parse_ports(&outputs, PortKind::Output, &mut node.outputs, |_, _, _| Ok(InputConstraints::default()))?;
 
-        // Create node based on variables
-        let mut node = Node {
-            id: Self::get_variable(&variables, "id")?,
-            title: Self::get_variable(&variables, "title")?,
-            description: Self::get_variable(&variables, "description")?,
-            category: Self::get_variable(&variables, "category")?,
-            inputs: KeyMap::new(),
-            outputs: KeyMap::new(),
-            default_hardcoded: HashMap::new(),
-        };
+    // Nodes working with a variable share their type variable with all nodes working with the same variable
+    if let Some(variable_input) = &node.variable_input {
+        match node.inputs.values.get(variable_input) {
+            Some(input) if input.ty.ty == DataType::Text && !input.variadic => {},
+            _ => return Err(MetadataError::new(&["variableInput"], format!("variableInput \"{}\" should be a text input of the node", variable_input))),
+        }
+        let generics = node.inputs.values.values().chain(node.outputs.values.values())
+            .filter_map(|port| port.ty.generic.as_ref())
+            .collect::<std::collections::BTreeSet<&String>>();
+        if generics.len() != 1 {
+            return Err(MetadataError::new(&["variableInput"], "node with variableInput has to use exactly one type variable, the type of the variable".to_string()));
+        }
+    }
 
-        // Add flow I/O
-        if !variables.contains_key("noFlowIn") && !variables.contains_key("pure") {
-            node.inputs.insert("__flow_in__".to_string(), NodeIO {
-                ty: NodeIOTy {
-                    ty: DataType::Flow,
-                    ..Default::default()
-                },
-                name: "flow_in".to_string(),
-            });
-        }
-        if !variables.contains_key("noFlowOut") && !variables.contains_key("pure") {
-            node.outputs.insert("__flow_out__".to_string(), NodeIO {
-                ty: NodeIOTy {
-                    ty: DataType::Flow,
-                    ..Default::default()
-                },
-                name: "flow_out".to_string(),
-            });
-        }
-
-        // Add inputs
-        let inputs = Self::get_variable::<rhai::Map>(&variables, "inputs")?;
-        let mut index_map = HashMap::<String, usize>::new();
-        let mut imap = HashMap::new();
-        for (name, input) in inputs.iter() {
-            let input = input.clone().cast::<rhai::Map>();
-            let input = input.iter()
-                .map(|(name, value)| (name.to_string(), value.clone_cast()))
-                .collect::<BTreeMap<String, Dynamic>>();
-            let ty = input.get("type").ok_or(NodeScriptLoadingError::InvalidIODeclaration(script.clone(), "Input type missing"))?.clone_cast::<String>();
-            let display_name = input.get("name").ok_or(NodeScriptLoadingError::InvalidIODeclaration(script.clone(), "Input name missing"))?.clone_cast::<String>();
-            let struct_tags = input.get("struct_tags");
-
-            let default = input.get("start_value");
-
-            let index = input.get("index");
-            if let Some(index) = index {
-                let index = index.clone_cast::<i64>();
-                index_map.insert(name.to_string(), index as usize);
-            } else {
-                index_map.insert(name.to_string(), 100);
+    // Type variables of outputs are resolved from the inputs, so an input has to use them too,
+    // unless the type comes from the variable
+    for (key, output) in node.outputs.values.iter() {
+        if let Some(generic) = output.ty.generic.as_ref().filter(|_| node.variable_input.is_none()) {
+            if !node.inputs.values.values().any(|input| input.ty.generic.as_ref() == Some(generic)) {
+                return Err(MetadataError::new(&["outputs", key, "type"], format!("type variable {} of output \"{}\" isn't used by any input, so it can't be resolved", generic, key)));
             }
-            
-            let io = NodeIO {
-                ty: NodeIOTy {
-                    ty: match ty.as_str() {
-                        "flow" => DataType::Flow,
-                        "number" => DataType::Number,
-                        "text" => DataType::Text,
-                        "boolean" => DataType::Boolean,
-                        "struct" => DataType::Structure,
-                        _ => DataType::Any,
-                    },
-                    struct_tags: if struct_tags.is_some() {
-                        let tags = struct_tags.unwrap().clone_cast::<rhai::Array>();
-                        tags.iter().map(|tag| tag.clone_cast::<String>()).collect::<Vec<String>>()
-                    } else {
-                        vec![]
-                    }
-                },
-                name: display_name.to_string(),
-            };
+        }
+    }
 
-            imap.insert(name.to_string(), io);
+    // Action generates code of the node, it gets the builder as its only argument
+    match ast.iter_functions().find(|f| f.name == "action") {
+        Some(action) if action.params.len() == 1 => {},
+        Some(action) => {
+            return Err(MetadataError::new(&["action"], format!("action function has to take exactly one parameter (builder), but it takes {}", action.params.len())));
+        },
+        None => {
+            return Err(MetadataError::new(&[], "missing action function".to_string()));
+        }
+    }
 
-            if default.is_some() {
-                node.default_hardcoded.insert(name.to_string(), default.unwrap().clone());
-            }
+    Ok(node)
+}
+
+#[derive(Clone, Copy)]
+enum PortKind {
+    Input,
+    Output,
+}
+
+impl PortKind {
+    fn constant(self) -> &'static str {
+        match self {
+            PortKind::Input => "inputs",
+            PortKind::Output => "outputs",
         }
+    }
 
-        // Add outputs
-        let outputs = Self::get_variable::<rhai::Map>(&variables, "outputs")?;
-        let mut output_index_map = HashMap::<String, usize>::new();
-        let mut omap = HashMap::new();
-        for (name, output) in outputs {
-            let output = output.clone().cast::<rhai::Map>();
-            let output = output.iter().map(|(k, v)| (k.to_string(), v)).collect::<HashMap<String, &Dynamic>>();
-            let ty = output.get("type").ok_or(NodeScriptLoadingError::InvalidIODeclaration(script.clone(), "Output type missing"))?.clone_cast::<String>();
-            let display_name = output.get("name").ok_or(NodeScriptLoadingError::InvalidIODeclaration(script.clone(), "Output name missing"))?.clone_cast::<String>();
-            let struct_type: Option<&&Dynamic> = output.get("struct_tags");
-
-            let index = output.get("index");
-            if let Some(index) = index {
-                let index = index.clone_cast::<i64>();
-                output_index_map.insert(name.to_string(), index as usize);
-            } else {
-                output_index_map.insert(name.to_string(), 100);
-            }
+    /// Name of a single port used in errors
+    fn name(self) -> &'static str {
+        match self {
+            PortKind::Input => "input",
+            PortKind::Output => "output",
+        }
+    }
+
+    fn fields(self) -> &'static [&'static str] {
+        match self {
+            PortKind::Input => INPUT_FIELDS,
+            PortKind::Output => OUTPUT_FIELDS,
+        }
+    }
+
+    fn types(self) -> &'static [&'static str] {
+        match self {
+            PortKind::Input => INPUT_TYPES,
+            PortKind::Output => OUTPUT_TYPES,
+        }
+    }
+}
+
+/// Parses declarations of inputs or outputs and adds them to `target` ordered by their indices.
+/// `extra` is called with every port declaration to read fields specific to the kind of ports
+fn parse_ports(
+    ports: &rhai::Map,
+    kind: PortKind,
+    target: &mut KeyMap<String, NodeIO>,
+    mut extra: impl FnMut(&str, DataType, &rhai::Map) -> Result<InputConstraints, MetadataError>,
+) -> Result<(), MetadataError> {
+    let mut map = HashMap::new();
+    let mut index_map = HashMap::new();
+
+    for (key, port) in ports.iter() {
+        let port_key = [kind.constant(), key.as_str()];
+        let port = cast::<rhai::Map>(port, &port_key, "a map")?;
+        let field_key = |field: &str| [kind.constant(), key.as_str(), field].map(str::to_string).to_vec();
+        let field_error = |field: &str, message: String| MetadataError { key: field_key(field), message };
+
+        // Key ending with [] declares a variadic input, the editor can add any number of its instances
+        let (port_name, variadic) = match key.strip_suffix("[]") {
+            Some(name) => (name, true),
+            None => (key.as_str(), false),
+        };
+        if variadic && matches!(kind, PortKind::Output) {
+            return Err(MetadataError::new(&port_key, format!("output \"{}\" can't be variadic, only inputs can", key)));
+        }
+        if port_name.is_empty() || port_name.contains(['[', ']']) {
+            return Err(MetadataError::new(&port_key, format!("invalid key of {} \"{}\", brackets are allowed only as the [] suffix of variadic inputs", kind.name(), key)));
+        }
+        if map.contains_key(port_name) {
+            return Err(MetadataError::new(&port_key, format!("{} \"{}\" is declared twice", kind.name(), port_name)));
+        }
 
-            omap.insert(name.to_string(), NodeIO {
-                ty: NodeIOTy {
-                    ty: match ty.as_str() {
-                        "flow" => DataType::Flow,
-                        "number" => DataType::Number,
-                        "text" => DataType::Text,
-                        "boolean" => DataType::Boolean,
-                        "struct" => DataType::Structure,
-                        _ => DataType::Any,
-                    },
-                    struct_tags: if struct_type.is_some() {
-                        let tags = struct_type.unwrap().clone_cast::<rhai::Array>();
-                        tags.iter().map(|tag| tag.clone_cast::<String>()).collect::<Vec<String>>()
-                    } else {
-                        vec![]
+        if let Some(field) = port.keys().find(|field| !kind.fields().contains(&field.as_str())) {
+            return Err(field_error(field, format!("unknown field \"{}\" in {} \"{}\" (expected one of {})", field, kind.name(), key, kind.fields().join(", "))));
+        }
+        let string = |field: &str| port.get(field)
+            .map(|value| cast::<String>(value, &[kind.constant(), key, field], "a string"))
+            .transpose();
+
+        let ty = string("type")?.ok_or_else(|| MetadataError::new(&port_key, format!("missing type of {} \"{}\"", kind.name(), key)))?;
+        // Type variables are resolved by the checker for every node in a flow, until then they are any
+        let generic = Some(ty.clone()).filter(|ty| is_type_variable(ty));
+        if !kind.types().contains(&ty.as_str()) && generic.is_none() {
+            return Err(field_error("type", format!("unknown type \"{}\" of {} \"{}\" (expected one of {} or a type variable like T)", ty, kind.name(), key, kind.types().join(", "))));
+        }
+        let ty = match ty.as_str() {
+            "flow" => DataType::Flow,
+            "number" => DataType::Number,
+            "text" => DataType::Text,
+            "boolean" => DataType::Boolean,
+            "struct" => DataType::Structure,
+            _ => DataType::Any,
+        };
+        let display_name = string("name")?.ok_or_else(|| MetadataError::new(&port_key, format!("missing name of {} \"{}\"", kind.name(), key)))?;
+
+        let struct_tags = match port.get("struct_tags") {
+            Some(_) if ty != DataType::Structure => {
+                return Err(field_error("struct_tags", format!("struct tags of {} \"{}\" are allowed only for struct type", kind.name(), key)));
+            },
+            Some(tags) => cast::<rhai::Array>(tags, &[kind.constant(), key, "struct_tags"], "an array of strings")?
+                .iter()
+                .map(|tag| cast::<String>(tag, &[kind.constant(), key, "struct_tags"], "an array of strings"))
+                .collect::<Result<Vec<String>, MetadataError>>()?,
+            None => vec![],
+        };
+        let index = match port.get("index") {
+            Some(index) => {
+                let index = cast::<i64>(index, &[kind.constant(), key, "index"], "an integer")?;
+                usize::try_from(index).map_err(|_| field_error("index", format!("index of {} \"{}\" can't be negative", kind.name(), key)))?
+            },
+            None => 100,
+        };
+
+        let constraints = extra(key, ty, &port)?;
+        map.insert(port_name.to_string(), NodeIO {
+            ty: NodeIOTy {
+                ty,
+                struct_tags,
+                ts_type: string("ts_type")?,
+                ts_import: string("ts_import")?,
+                generic,
+            },
+            name: display_name,
+            constraints,
+            variadic,
+        });
+        index_map.insert(port_name.to_string(), index);
+    }
+
+    target.extend_from_map_and_keymap(map, index_map);
+    Ok(())
+}
+
+/// Parses constraints of the values hardcoded in the editor, every constraint is allowed only for some input types
+fn parse_constraints(key: &str, ty: DataType, port: &rhai::Map) -> Result<InputConstraints, MetadataError> {
+    let field_key = |field: &'static str| ["inputs", key, field];
+    let only_for = |field: &'static str, types: &[DataType]| match port.contains_key(field) && !types.contains(&ty) {
+        true => Err(MetadataError::new(&field_key(field), format!(
+            "{} of input \"{}\" is allowed only for {} inputs", field, key, types.iter().map(DataType::name).collect::<Vec<&str>>().join(" and ")
+        ))),
+        false => Ok(()),
+    };
+    only_for("min", &[DataType::Number])?;
+    only_for("max", &[DataType::Number])?;
+    only_for("max_length", &[DataType::Text])?;
+    only_for("pattern", &[DataType::Text])?;
+    only_for("multiline", &[DataType::Text])?;
+    only_for("choices", &[DataType::Text, DataType::Number])?;
+
+    let number = |field: &'static str| port.get(field)
+        .map(|value| match value.as_float() {
+            Ok(value) => Ok(value),
+            Err(_) => cast::<i64>(value, &field_key(field), "a number").map(|value| value as f64),
+        })
+        .transpose();
+    let flag = |field: &'static str| port.get(field)
+        .map(|value| cast::<bool>(value, &field_key(field), "a boolean"))
+        .transpose()
+        .map(Option::unwrap_or_default);
+
+    let constraints = InputConstraints {
+        min: number("min")?,
+        max: number("max")?,
+        max_length: port.get("max_length")
+            .map(|value| {
+                let length = cast::<i64>(value, &field_key("max_length"), "an integer")?;
+                usize::try_from(length).map_err(|_| MetadataError::new(&field_key("max_length"), format!("max_length of input \"{}\" can't be negative", key)))
+            })
+            .transpose()?,
+        pattern: port.get("pattern")
+            .map(|value| {
+                let pattern = cast::<String>(value, &field_key("pattern"), "a string")?;
+                Pattern::new(&pattern).map_err(|err| {
+                    MetadataError::new(&field_key("pattern"), format!("pattern of input \"{}\" is not a valid regex: {}", key, err))
+                })
+            })
+            .transpose()?,
+        choices: match port.get("choices") {
+            Some(choices) => cast::<rhai::Array>(choices, &field_key("choices"), "an array")?
+                .iter()
+                .map(|choice| {
+                    let valid = match ty {
+                        DataType::Number => choice.is_int() || choice.is_float(),
+                        _ => choice.is_string(),
+                    };
+                    match valid {
+                        true => Ok(serde_json::to_value(choice).unwrap_or_default()),
+                        false => Err(MetadataError::new(&field_key("choices"), format!("choices of input \"{}\" should be {}s", key, ty.name()))),
                     }
-                },
-                name: display_name.to_string(),
-            });
+                })
+                .collect::<Result<Vec<serde_json::Value>, MetadataError>>()?,
+            None => vec![],
+        },
+        multiline: flag("multiline")?,
+        required: flag("required")?,
+    };
+
+    if let (Some(min), Some(max)) = (constraints.min, constraints.max) {
+        if min > max {
+            return Err(MetadataError::new(&field_key("min"), format!("min of input \"{}\" is greater than its max", key)));
         }
+    }
+    if port.contains_key("choices") && constraints.choices.is_empty() {
+        return Err(MetadataError::new(&field_key("choices"), format!("choices of input \"{}\" can't be empty", key)));
+    }
+    Ok(constraints)
+}
 
-        node.inputs.extend_from_map_and_keymap(imap, index_map);
-        node.outputs.extend_from_map_and_keymap(omap, output_index_map);
+/// Type variables start with an uppercase letter, the same as type parameters in TypeScript
+fn is_type_variable(ty: &str) -> bool {
+    ty.starts_with(|c: char| c.is_ascii_uppercase()) && ty.chars().all(|c| c.is_ascii_alphanumeric())
+}
 
-        Ok(node)
+/// Checks if the text is a CSS hex color (`#rgb` or `#rrggbb`)
+fn is_hex_color(color: &str) -> bool {
+    color.strip_prefix('#')
+        .is_some_and(|hex| matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Gets text of the `//!` comments of the script, without the comment markers
+fn doc_comment(ast: &rhai::AST) -> Option<String> {
+    let docs = ast.doc().lines()
+        .map(|line| line.trim_start_matches("//!"))
+        .map(|line| line.strip_prefix(' ').unwrap_or(line))
+        .collect::<Vec<&str>>()
+        .join("\n");
+    let docs = docs.trim();
+    (!docs.is_empty()).then(|| docs.to_string())
+}
+
+/// Checks that start value of an input has the same type as the input
+fn check_start_value(value: &Dynamic, ty: DataType, key: &str) -> Result<(), MetadataError> {
+    let is_number = value.is_int() || value.is_float();
+    let (valid, expected) = match ty {
+        DataType::Text => (value.is_string(), "a string"),
+        DataType::Number => (is_number, "a number"),
+        DataType::Boolean => (value.is_bool(), "a boolean"),
+        DataType::Any => (value.is_string() || is_number || value.is_bool(), "a string, number or boolean"),
+        DataType::Structure | DataType::Flow => {
+            return Err(MetadataError::new(&["inputs", key, "start_value"], format!("input \"{}\" can't have a start value, start values are supported only for text, number, boolean and any inputs", key)));
+        }
+    };
+    if !valid {
+        return Err(MetadataError::new(&["inputs", key, "start_value"], format!("start value of input \"{}\" should be {}", key, expected)));
+    }
+    Ok(())
+}
+
+/// Converts value declared in a script, `key` is used in the error if the value has a different type
+fn cast<T: Clone + 'static>(value: &Dynamic, key: &[&str], expected: &'static str) -> Result<T, MetadataError> {
+    value.clone().try_cast::<T>()
+        .ok_or_else(|| MetadataError::new(key, format!("{} should be {}", key.join("."), expected)))
+}
+
+/// Finds line of the value at `key` in the script, or of the closest parent of the value that exists
+fn find_line(ast: &rhai::AST, key: &[String]) -> Option<usize> {
+    let (first, rest) = key.split_first()?;
+
+    if let Some(function) = ast.iter_fn_def().find(|f| f.name == first.as_str()) {
+        return function.body.position().line();
+    }
+
+    let (ident, mut expr) = ast.statements().iter().find_map(|stmt| match stmt {
+        Stmt::Var(var, ..) if var.0.name == first.as_str() => Some((&var.0, &var.1)),
+        _ => None,
+    })?;
+    let mut position = ident.pos;
+    for segment in rest {
+        let entry = match expr {
+            Expr::Map(map, ..) => map.0.iter().find(|(name, _)| name.name == segment.as_str()),
+            _ => None,
+        };
+        match entry {
+            Some((name, value)) => {
+                position = name.pos;
+                expr = value;
+            },
+            None => break,
+        }
     }
+    position.line()
 }
 
 /// Custom map type that preserves insertion order
@@ -216,11 +574,17 @@ impl<K, V> Debug for KeyMap<K, V> where K: Debug, V: Debug {
     }
 }
 
+impl<K, V> Default for KeyMap<K, V> where K: Clone + Hash + Eq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<K, V> KeyMap<K, V> where K: Clone + Hash + Eq {
     /// Returns a new KeyMap based on the given map, and a map of keys to their insertion order
     pub fn from_map_and_keymap(map: HashMap<K, V>, keymap: HashMap<K, usize>) -> Self {
         let mut keys = keymap.into_iter().collect::<Vec<(K, usize)>>();
-        keys.sort_by(|(_, a), (_, b)| a.cmp(b));
+        keys.sort_by_key(|(_, index)| *index);
         let keys = keys.into_iter().map(|(k, _)| k).collect::<Vec<K>>();
         Self {
             keys,
@@ -243,7 +607,7 @@ impl<K, V> KeyMap<K, V> where K: Clone + Hash + Eq {
     /// Extends the KeyMap with the given map and keymap
     pub fn extend_from_map_and_keymap(&mut self, map: HashMap<K, V>, keymap: HashMap<K, usize>) {
         let mut keys = keymap.into_iter().collect::<Vec<(K, usize)>>();
-        keys.sort_by(|(_, a), (_, b)| a.cmp(b));
+        keys.sort_by_key(|(_, index)| *index);
         let keys = keys.into_iter().map(|(k, _)| k).collect::<Vec<K>>();
         self.keys.extend(keys);
         self.values.extend(map);
@@ -256,13 +620,49 @@ pub struct Node {
     pub title: String,
     pub description: String,
     pub category: String,
+    /// Color of the node header in the editor
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub color: Option<String>,
+    /// Image shown next to the title in the editor
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub icon: Option<String>,
+    /// Additional words the node can be found by in the editor
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub keywords: Vec<String>,
+    /// Node may still change or be removed
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub experimental: bool,
+    /// Node shouldn't be used anymore, projects using it get a warning
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub deprecated: Option<Deprecation>,
     pub inputs: KeyMap<String, NodeIO>,
     pub outputs: KeyMap<String, NodeIO>,
     #[serde(rename = "defaultHardcoded")]
     pub default_hardcoded: HashMap<String, Dynamic>,
+    /// Package the node comes from, nodes outside of packages don't have it
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub package: Option<Package>,
+    /// Long-form Markdown documentation from the `//!` comments of the script
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub docs: Option<String>,
+    /// Input with the name of the user variable the node works with,
+    /// the type variable of the node is then the type of the variable
+    #[serde(rename = "variableInput", skip_serializing_if = "Option::is_none", default)]
+    pub variable_input: Option<String>,
+    /// Values of different types for the same type variable are only a warning,
+    /// used by nodes that accepted any values before they had type variables
+    #[serde(rename = "lenientTypes", skip_serializing_if = "std::ops::Not::not", default)]
+    pub lenient_types: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Deprecation {
+    /// Id of the node that should be used instead
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub replacement: Option<String>,
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum DataType {
     Flow = 0,
     Number = 1,
@@ -273,6 +673,20 @@ pub enum DataType {
     Any = 5,
 }
 
+impl DataType {
+    /// Name of the type used in node scripts
+    pub fn name(&self) -> &'static str {
+        match self {
+            DataType::Flow => "flow",
+            DataType::Number => "number",
+            DataType::Text => "text",
+            DataType::Boolean => "boolean",
+            DataType::Structure => "struct",
+            DataType::Any => "any",
+        }
+    }
+}
+
 impl serde::Serialize for DataType {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
         serializer.serialize_u8(*self as u8)
@@ -293,50 +707,389 @@ impl<'a> serde::Deserialize<'a> for DataType {
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone)]
 pub struct NodeIOTy {
     #[serde(rename = "type")]
-    ty: DataType,
+    pub ty: DataType,
     #[serde(rename = "structTags")]
-    struct_tags: Vec<String>,
+    pub struct_tags: Vec<String>,
+    /// TypeScript type overriding the one derived from `ty` and `struct_tags`
+    #[serde(rename = "tsType", skip_serializing_if = "Option::is_none", default)]
+    pub ts_type: Option<String>,
+    /// Module that `ts_type` has to be imported from
+    #[serde(rename = "tsImport", skip_serializing_if = "Option::is_none", default)]
+    pub ts_import: Option<String>,
+    /// Type variable of the port, ports of a node with the same variable have the same type.
+    /// `ty` of these ports is any, the checker binds the variable from the connections in a flow
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub generic: Option<String>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct NodeIO {
     #[serde(rename = "type")]
-    ty: NodeIOTy,
-    name: String,
+    pub ty: NodeIOTy,
+    pub name: String,
+    #[serde(skip_serializing_if = "InputConstraints::is_empty", default)]
+    pub constraints: InputConstraints,
+    /// Input can have any number of instances, each flow node stores how many it has
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub variadic: bool,
+}
+
+/// Key of an instance of a variadic input, instances are numbered from zero
+pub fn variadic_key(key: &str, index: usize) -> String {
+    format!("{}[{}]", key, index)
 }
 
-/// Loads all nodes from the given path and its subdirectories
-pub fn load_all_nodes(path: PathBuf) -> Result<Vec<Node>, NodeScriptLoadingError> {
-    let mut nodes: Vec<Node> = Vec::new();
+/// Splits key of a variadic input instance into the key of the input and the index of the instance.
+/// Only keys created by `variadic_key` are accepted
+pub fn parse_variadic_key(key: &str) -> Option<(&str, usize)> {
+    let (key, index) = key.strip_suffix(']')?.rsplit_once('[')?;
+    if key.is_empty() || index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((key, index.parse().ok()?))
+}
 
-    let mut loader = NodeScriptLoader::new();
+/// Constraints of the value hardcoded in the editor, only inputs have them.
+/// The editor also uses them to pick the widget for the value
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct InputConstraints {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max: Option<f64>,
+    /// Maximum number of characters of text
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_length: Option<usize>,
+    /// Regex the whole text has to match
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub pattern: Option<Pattern>,
+    /// Values the input can have, the editor shows a select for them
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub choices: Vec<serde_json::Value>,
+    /// Text can have multiple lines, the editor shows a text area for it
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub multiline: bool,
+    /// Input has to be connected or have a value
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub required: bool,
+}
 
-    for entry in fs::read_dir(path).unwrap() {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        
-        if path.is_dir() {
-            nodes.extend(load_all_nodes(path)?);
-        } else {
-            let extension = path.extension().unwrap_or_default().to_str().unwrap_or_default();
+impl InputConstraints {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
 
-            if extension == "rhai" {
-                let node = loader.load(path)?;
-                nodes.push(node);
+    /// Checks the value of an input that is not connected, an empty value is the same as no value
+    pub fn check_input(&self, hardcoded: Option<&serde_json::Value>, connected: bool) -> Result<(), String> {
+        if connected {
+            return Ok(());
+        }
+        match hardcoded.filter(|value| !value.is_null() && value.as_str() != Some("")) {
+            Some(value) => self.check(value),
+            None if self.required => Err("input is required, connect it or set its value".to_string()),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks value against the constraints, returns what is wrong with it
+    pub fn check(&self, value: &serde_json::Value) -> Result<(), String> {
+        if let Some(number) = value.as_f64() {
+            if self.min.is_some_and(|min| number < min) {
+                return Err(format!("{} is less than the minimum {}", number, self.min.unwrap()));
+            }
+            if self.max.is_some_and(|max| number > max) {
+                return Err(format!("{} is greater than the maximum {}", number, self.max.unwrap()));
+            }
+        }
+        if let Some(text) = value.as_str() {
+            if let Some(max_length) = self.max_length {
+                if text.chars().count() > max_length {
+                    return Err(format!("text is longer than {} characters", max_length));
+                }
+            }
+            if let Some(pattern) = &self.pattern {
+                if !pattern.is_match(text) {
+                    return Err(format!("\"{}\" doesn't match the pattern {}", text, pattern));
+                }
             }
         }
+        let is_choice = |choice: &serde_json::Value| match (choice.as_f64(), value.as_f64()) {
+            (Some(choice), Some(value)) => choice == value,
+            _ => choice == value,
+        };
+        if !self.choices.is_empty() && !self.choices.iter().any(is_choice) {
+            let choices = self.choices.iter().map(|choice| choice.to_string()).collect::<Vec<String>>();
+            return Err(format!("{} is not one of {}", value, choices.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+/// Regex of the `pattern` constraint, compiled once when the script is loaded.
+/// It is serialized as the regex source, so the editor can use it in the pattern attribute of inputs
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    /// Pattern has to match the whole text, the same as the pattern attribute of inputs in the editor
+    anchored: Regex,
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            source: source.to_string(),
+            anchored: Regex::new(&format!("^(?:{})$", source))?,
+        })
     }
 
-    Ok(nodes)
+    pub fn is_match(&self, text: &str) -> bool {
+        self.anchored.is_match(text)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl serde::Serialize for Pattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Pattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+        let source = String::deserialize(deserializer)?;
+        Self::new(&source).map_err(serde::de::Error::custom)
+    }
 }
 
 /// Exports the given nodes to the given path
-pub fn export_node_declarations(nodes: Vec<Node>, target_path: PathBuf) {
-    fs::create_dir_all(target_path.parent().unwrap()).unwrap();
-    let file = File::create(target_path).unwrap();
+pub fn export_node_declarations(nodes: &NodeRegistry, target_path: PathBuf) -> std::io::Result<()> {
+    if let Some(parent) = target_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = File::create(target_path)?;
+
+    serde_json::ser::to_writer_pretty(file, &nodes.declarations())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compiler::shared_engine, registry::RegisteredNode};
+
+    use super::*;
+
+    /// Loads script with the given inputs and rest of the script after them
+    fn load(inputs: &str, rest: &str) -> Result<Node, NodeScriptLoadingError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("node.rhai");
+        std::fs::write(&path, format!(
+            "const id = \"test\";\nconst title = \"Test\";\nconst description = \"\";\nconst category = \"Test\";\nconst outputs = #{{}};\nconst inputs = #{{\n{}\n}};\n{}\n",
+            inputs, rest
+        )).unwrap();
+        RegisteredNode::load(path, None, &shared_engine()).map(|node| std::sync::Arc::try_unwrap(node.metadata).unwrap())
+    }
+
+    const ACTION: &str = "fn action(builder) {}";
+
+    /// Asserts that loading fails on the line with the message containing `message`
+    fn assert_invalid(result: Result<Node, NodeScriptLoadingError>, expected_line: usize, expected_message: &str) {
+        match result {
+            Err(NodeScriptLoadingError::InvalidMetadata { line, message, .. }) => {
+                assert_eq!(line, Some(expected_line), "{}", message);
+                assert!(message.contains(expected_message), "{}", message);
+            },
+            other => panic!("expected invalid metadata, got {:?}", other.map(|node| node.id)),
+        }
+    }
+
+    #[test]
+    fn loads_valid_script() {
+        let node = load("    text: #{ name: \"Text\", type: \"text\", index: 0, start_value: \"hi\", max_length: 10 }", ACTION).unwrap();
+        assert_eq!(node.inputs.keys, vec!["__flow_in__", "text"]);
+        assert_eq!(node.inputs.values["text"].constraints.max_length, Some(10));
+        assert!(node.default_hardcoded.contains_key("text"));
+    }
+
+    #[test]
+    fn rejects_invalid_type() {
+        assert_invalid(load("    text: #{ name: \"Text\", type: \"txt\", index: 0 }", ACTION), 7, "txt");
+    }
+
+    #[test]
+    fn rejects_invalid_index() {
+        assert_invalid(load("    text: #{ name: \"Text\", type: \"text\", index: \"0\" }", ACTION), 7, "index");
+    }
+
+    #[test]
+    fn rejects_mismatched_start_value() {
+        let inputs = "    a: #{ name: \"A\", type: \"text\", index: 0 },\n    b: #{ name: \"B\", type: \"number\", index: 1, start_value: \"one\" }";
+        assert_invalid(load(inputs, ACTION), 8, "start");
+    }
+
+    #[test]
+    fn rejects_invalid_action() {
+        let input = "    text: #{ name: \"Text\", type: \"text\", index: 0 }";
+        match load(input, "") {
+            Err(NodeScriptLoadingError::InvalidMetadata { line: None, message, .. }) => assert!(message.contains("missing action"), "{}", message),
+            other => panic!("expected missing action, got {:?}", other.map(|node| node.id)),
+        }
+        assert_invalid(load(input, "\nfn action(builder, extra) {}"), 10, "exactly one parameter");
+    }
+
+    #[test]
+    fn rejects_unknown_constants() {
+        assert_invalid(load("", "const purr;\nfn action(builder) {}"), 9, "unknown constant \"purr\"");
+    }
+
+    #[test]
+    fn loads_presentation_metadata() {
+        let node = load("", "const color = \"#5865f2\";\nconst icon = \"wave\";\nconst keywords = [\"hello\"];\nconst experimental;\nconst deprecated = \"greet\";\nfn action(builder) {}").unwrap();
+        assert_eq!(node.color.as_deref(), Some("#5865f2"));
+        assert_eq!(node.icon.as_deref(), Some("wave"));
+        assert_eq!(node.keywords, vec!["hello"]);
+        assert!(node.experimental);
+        assert_eq!(node.deprecated.unwrap().replacement.as_deref(), Some("greet"));
+
+        let node = load("", "const deprecated;\nconst experimental = false;\nfn action(builder) {}").unwrap();
+        assert!(node.deprecated.unwrap().replacement.is_none());
+        assert!(!node.experimental);
+    }
+
+    #[test]
+    fn rejects_invalid_presentation_metadata() {
+        assert_invalid(load("", "const color = \"blue\";\nfn action(builder) {}"), 9, "hex color");
+        assert_invalid(load("", "\nconst keywords = [\"a\", 1];\nfn action(builder) {}"), 10, "an array of strings");
+        assert_invalid(load("", "const deprecated = true;\nfn action(builder) {}"), 9, "id of the replacement node");
+    }
+
+    fn constraints(port: &str) -> InputConstraints {
+        let node = load(&format!("    value: #{{ name: \"Value\", index: 0, {} }}", port), ACTION).unwrap();
+        node.inputs.values["value"].constraints.clone()
+    }
+
+    #[test]
+    fn checks_number_range() {
+        let range = constraints("type: \"number\", min: 1, max: 2.5");
+        assert!(range.check(&serde_json::json!(1)).is_ok());
+        assert!(range.check(&serde_json::json!(2.5)).is_ok());
+        assert!(range.check(&serde_json::json!(0.5)).unwrap_err().contains("less than the minimum 1"));
+        assert!(range.check(&serde_json::json!(3)).unwrap_err().contains("greater than the maximum 2.5"));
+
+        assert_invalid(load("    value: #{ name: \"Value\", type: \"number\", index: 0, min: 2, max: 1 }", ACTION), 7, "greater than its max");
+        assert_invalid(load("    value: #{ name: \"Value\", type: \"text\", index: 0, min: 2 }", ACTION), 7, "only for number inputs");
+    }
 
-    serde_json::ser::to_writer_pretty(file, &nodes).unwrap();
-}
\ No newline at end of file
+    #[test]
+    fn checks_text_length_and_pattern() {
+        let text = constraints("type: \"text\", max_length: 3, pattern: \"[a-z]+\"");
+        assert_eq!(text.pattern.as_ref().map(Pattern::as_str), Some("[a-z]+"));
+        assert!(text.check(&serde_json::json!("abc")).is_ok());
+        assert!(text.check(&serde_json::json!("abcd")).unwrap_err().contains("longer than 3"));
+        // Pattern has to match the whole text
+        assert!(text.check(&serde_json::json!("ab1")).unwrap_err().contains("doesn't match the pattern [a-z]+"));
+        // Length is counted in characters, not bytes
+        assert!(constraints("type: \"text\", max_length: 2").check(&serde_json::json!("éé")).is_ok());
+
+        // Declarations keep the pattern as it was written
+        let declaration = serde_json::to_value(&text).unwrap();
+        assert_eq!(declaration["pattern"], "[a-z]+");
+        assert_eq!(serde_json::from_value::<InputConstraints>(declaration).unwrap(), text);
+
+        assert_invalid(load("    value: #{ name: \"Value\", type: \"text\", index: 0, pattern: \"[a-z\" }", ACTION), 7, "not a valid regex");
+    }
+
+    #[test]
+    fn checks_choices() {
+        let numbers = constraints("type: \"number\", choices: [1, 2.5]");
+        assert!(numbers.check(&serde_json::json!(1.0)).is_ok());
+        assert!(numbers.check(&serde_json::json!(2.5)).is_ok());
+        assert!(numbers.check(&serde_json::json!(3)).unwrap_err().contains("is not one of 1, 2.5"));
+
+        let texts = constraints("type: \"text\", choices: [\"a\", \"b\"]");
+        assert!(texts.check(&serde_json::json!("a")).is_ok());
+        assert!(texts.check(&serde_json::json!("c")).is_err());
+
+        assert_invalid(load("    value: #{ name: \"Value\", type: \"number\", index: 0, choices: [\"a\"] }", ACTION), 7, "should be numbers");
+        assert_invalid(load("    value: #{ name: \"Value\", type: \"text\", index: 0, choices: [] }", ACTION), 7, "can't be empty");
+    }
+
+    #[test]
+    fn checks_hardcoded_values_of_unconnected_inputs() {
+        let required = constraints("type: \"text\", required: true, max_length: 1");
+        assert!(required.check_input(None, false).unwrap_err().contains("required"));
+        // Empty value is the same as no value
+        assert!(required.check_input(Some(&serde_json::json!("")), false).is_err());
+        assert!(required.check_input(Some(&serde_json::Value::Null), false).is_err());
+        assert!(required.check_input(Some(&serde_json::json!("a")), false).is_ok());
+        assert!(required.check_input(Some(&serde_json::json!("ab")), false).is_err());
+
+        // Connected inputs get their value from the connection, the hardcoded one is not used
+        assert!(required.check_input(None, true).is_ok());
+        assert!(required.check_input(Some(&serde_json::json!("ab")), true).is_ok());
+
+        let optional = constraints("type: \"text\", max_length: 1");
+        assert!(optional.check_input(None, false).is_ok());
+    }
+
+    #[test]
+    fn start_values_have_to_satisfy_constraints() {
+        assert_invalid(load("    value: #{ name: \"Value\", type: \"number\", index: 0, min: 1, start_value: 0 }", ACTION), 7, "start value of input \"value\" is invalid");
+    }
+
+    #[test]
+    fn variable_nodes_need_a_text_input_and_one_type_variable() {
+        let name = "    name: #{ name: \"Name\", type: \"text\", index: 0 }";
+        let get = "const outputs = #{ value: #{ name: \"Value\", type: \"T\" } };\nfn action(builder) {}";
+        let script = |rest: &str| {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("node.rhai");
+            std::fs::write(&path, format!(
+                "const id = \"get\";\nconst title = \"Get\";\nconst description = \"\";\nconst category = \"Test\";\nconst inputs = #{{\n{}\n}};\n{}\n",
+                name, rest
+            )).unwrap();
+            RegisteredNode::load(path, None, &shared_engine()).map(|node| std::sync::Arc::try_unwrap(node.metadata).unwrap())
+        };
+        // Output type variable doesn't need an input, it is the type of the variable
+        let node = script(&format!("const variableInput = \"name\";\n{}", get)).unwrap();
+        assert_eq!(node.variable_input.as_deref(), Some("name"));
+
+        assert_invalid(script(&format!("const variableInput = \"value\";\n{}", get)), 8, "should be a text input");
+        assert_invalid(script(get), 8, "isn't used by any input");
+        let two = "const outputs = #{ a: #{ name: \"A\", type: \"T\" }, b: #{ name: \"B\", type: \"U\" } };\nfn action(builder) {}";
+        assert_invalid(script(&format!("const variableInput = \"name\";\n{}", two)), 8, "exactly one type variable");
+    }
+
+    #[test]
+    fn variadic_keys_round_trip() {
+        for (key, index) in [("values", 0), ("values", 12), ("a[b]", 3)] {
+            assert_eq!(parse_variadic_key(&variadic_key(key, index)), Some((key, index)));
+        }
+        assert_eq!(variadic_key("values", 1), "values[1]");
+    }
+
+    #[test]
+    fn rejects_keys_that_are_not_variadic() {
+        for key in ["values", "values[]", "values[x]", "values[-1]", "values[+1]", "values[1", "[1]", "values[1]x"] {
+            assert_eq!(parse_variadic_key(key), None, "{}", key);
+        }
+    }
+}
@@ -1,21 +1,50 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 
-use compiler::{NodesJSCompiler, AvailableNode, upgrade_engine};
-use loader::{load_all_nodes, export_node_declarations, Node};
-use clap::{Parser, Subcommand, arg};
+use cache::{CompileCache, DiskCache, MemoryCache};
+use config::{Config, BuildConfig, PackagesConfig, ResolvedConfig, ServerConfig, ConfigError, split_paths, CONFIG_FILE, DEFAULT_HOST, DEFAULT_PORT};
+use compiler::{NodesJSCompiler, shared_engine};
+use output::{Output, OutputFormat, EXIT_FAILURE, EXIT_USAGE, EXIT_LOAD, EXIT_COMPILE, EXIT_EXPORT};
+use project::{upgrade_project_file, DisbotterProjectData, FORMAT_VERSION};
+use check::{check_project, check_project_flows};
+use loader::export_node_declarations;
+use docs::export_node_docs;
+use registry::NodeRegistry;
+use package::npm_dependencies;
+use market::{IndexSource, DEFAULT_DIR};
+use watch::{watch, WatchOptions};
+use clap::{Args, Parser, Subcommand};
 use colored::*;
-use rhai::Engine;
+use serde_json::json;
 
 // ===< Module imports >=== //
 pub mod loader;
 pub mod builder;
 pub mod compiler;
+pub mod typescript;
+pub mod cache;
+pub mod graph;
+pub mod scope;
+pub mod project;
+pub mod check;
+pub mod config;
+pub mod registry;
+pub mod package;
+pub mod market;
+pub mod docs;
 mod server;
+mod output;
+mod watch;
 
 // ===< Main CLI >=== //
 #[derive(Parser)]
 #[command(author, about, version)]
 struct Cli {
+    #[arg(long, value_enum, global = true, default_value_t = OutputFormat::Text, help="Output format, json prints a single JSON object with the result")]
+    format: OutputFormat,
+    #[arg(long, global = true, help="Path to the configuration file (by default disbotter.toml is looked up in the working directory and its parents)")]
+    config: Option<PathBuf>,
+    #[arg(long, global = true, help="Build profile from the configuration file to use")]
+    profile: Option<String>,
     #[command(subcommand)]
     command: Option<Commands>
 }
@@ -25,18 +54,40 @@ enum Commands {
     #[command(name="gen-node-declarations", about="Generates node declarations for the web editor")]
     GenNodeDeclarations {
         #[arg(short, long, help="Path to the directory/directories containing the nodes")]
-        path: String,
+        path: Option<String>,
         #[arg(short, long, help="Path to the output file")]
         output: String
     },
+    #[command(name="gen-node-docs", about="Generates Markdown reference of the nodes")]
+    GenNodeDocs {
+        #[arg(short, long, help="Path to the directory/directories containing the nodes")]
+        path: Option<String>,
+        #[arg(short, long, help="Path to the output directory")]
+        output: PathBuf
+    },
     #[command(name="compile", about="Compile a project from a .dbp file")]
     Compile {
+        #[command(flatten)]
+        build: BuildArgs,
+        #[arg(short, long, help="Keep running and compile the project again whenever the project or node scripts change")]
+        watch: bool
+    },
+    #[command(name="watch", about="Compile a project and compile it again whenever the project or node scripts change")]
+    Watch {
+        #[command(flatten)]
+        build: BuildArgs
+    },
+    #[command(name="check", about="Check a project for errors without writing any output")]
+    Check {
         #[arg(short, long, help="Path to the .dbp file")]
-        path: String,
-        #[arg(short, long, help="Path to the output directory")]
-        output: String,
+        path: Option<String>,
         #[arg(short, long, help="Path to the directory/directories containing the nodes")]
-        nodes: String
+        nodes: Option<String>
+    },
+    #[command(name="upgrade", about="Upgrade .dbp files to the current project format in place")]
+    Upgrade {
+        #[arg(help="Paths to the .dbp files", required = true)]
+        paths: Vec<PathBuf>
     },
     #[command(name="init", about="Initialize a new project", aliases=&["new", "create"])]
     Init {
@@ -48,64 +99,369 @@ enum Commands {
     #[command(name="server", about="Start the REST API server")]
     Server {
         #[arg(short, long, help="Path to the directory/directories containing the nodes")]
-        nodes: String,
+        nodes: Option<String>,
         #[arg(short, long, help="Path to the directory containing the projects")]
         projects: Option<PathBuf>,
+        #[arg(long, help="Address the server listens on (default 127.0.0.1)")]
+        host: Option<String>,
+        #[arg(long, help="Port the server listens on (default 3000)")]
+        port: Option<u16>,
+    },
+    #[command(name="nodes", about="Install node packages from a package index")]
+    Nodes {
+        #[command(flatten)]
+        packages: PackagesArgs,
+        #[command(subcommand)]
+        command: NodesCommands
+    },
+    #[command(name="config", about="Inspect the configuration")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands
+    }
+}
+
+/// Arguments shared by the commands that compile a project
+#[derive(Args)]
+struct BuildArgs {
+    #[arg(short, long, help="Path to the .dbp file")]
+    path: Option<String>,
+    #[arg(short, long, help="Path to the output directory")]
+    output: Option<String>,
+    #[arg(short, long, help="Path to the directory/directories containing the nodes")]
+    nodes: Option<String>,
+    #[arg(long, help="Overwrite generated files even if they were modified by hand (modified files are backed up)")]
+    force: bool,
+    #[arg(long, help="Compile all commands from scratch, without using or updating the cache")]
+    no_cache: bool
+}
+
+impl BuildArgs {
+    /// Converts the arguments to settings overriding the configuration file
+    fn into_config(self) -> BuildConfig {
+        BuildConfig {
+            nodes: self.nodes.as_deref().map(split_paths),
+            project: self.path.map(PathBuf::from),
+            output: self.output.map(PathBuf::from),
+            force: self.force.then_some(true),
+            no_cache: self.no_cache.then_some(true),
+        }
+    }
+}
+
+/// Arguments shared by the commands that install node packages
+#[derive(Args)]
+struct PackagesArgs {
+    #[arg(long, global = true, help="Package index, a directory or an HTTP(S) URL with index.json")]
+    index: Option<String>,
+    #[arg(long, global = true, help="Directory the packages are installed to (default ./nodes)")]
+    dir: Option<PathBuf>
+}
+
+impl PackagesArgs {
+    /// Converts the arguments to settings overriding the configuration file
+    fn into_config(self) -> PackagesConfig {
+        PackagesConfig {
+            index: self.index,
+            dir: self.dir,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum NodesCommands {
+    #[command(name="add", about="Install a package, or change the installed version")]
+    Add {
+        #[arg(help="Package name, optionally with a version requirement (name@^1.2)")]
+        package: String
+    },
+    #[command(name="remove", about="Remove an installed package")]
+    Remove {
+        #[arg(help="Package name")]
+        name: String
+    },
+    #[command(name="update", about="Update installed packages to the newest versions matching the requirements they were added with")]
+    Update {
+        #[arg(help="Packages to update, all installed packages if none are given")]
+        names: Vec<String>
+    }
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    #[command(name="show", about="Print the configuration merged from the configuration file, the selected profile and the given flags")]
+    Show {
+        #[command(flatten)]
+        build: BuildArgs,
+        #[command(flatten)]
+        packages: PackagesArgs,
+        #[arg(long, help="Directory containing the projects of the server")]
+        projects: Option<PathBuf>,
+        #[arg(long, help="Address the server listens on")]
+        host: Option<String>,
+        #[arg(long, help="Port the server listens on")]
+        port: Option<u16>,
+    }
+}
+
+/// Returns directory where compiled commands of the project are cached,
+/// every project gets its own directory inside `.disbotter-cache` next to the project file
+fn cache_dir(project_path: &std::path::Path) -> PathBuf {
+    let stem = project_path.file_stem().unwrap_or_default();
+    project_path.parent().unwrap_or(std::path::Path::new(".")).join(".disbotter-cache").join(stem)
+}
+
+/// Checks if JSON output was requested, used when the arguments couldn't be parsed
+fn wants_json() -> bool {
+    let args = std::env::args().collect::<Vec<String>>();
+    args.iter().any(|arg| arg == "--format=json") || args.windows(2).any(|w| w[0] == "--format" && w[1] == "json")
+}
+
+/// Loads configuration file and the selected profile, exits if it can't be loaded
+fn load_config(file: Option<PathBuf>, profile: Option<String>, output: &Output) -> ResolvedConfig {
+    match Config::load(file, profile) {
+        Ok(config) => config,
+        Err(err) => {
+            let code = if matches!(err, ConfigError::UnknownProfile(_)) { EXIT_USAGE } else { EXIT_LOAD };
+            output.fail(code, "Failed to load configuration".red().to_string(), &[err.to_pretty()], json!({}));
+        }
+    }
+}
+
+/// Unwraps value that has to be given either on the command line or in the configuration file
+fn required<T>(value: Option<T>, flag: &str, key: &str, output: &Output) -> T {
+    match value {
+        Some(value) => value,
+        None => output.fail(EXIT_USAGE, format!("{} {} (or {} in {})", "Missing argument:".red(), flag.yellow(), key.yellow(), CONFIG_FILE), &[], json!({})),
+    }
+}
+
+/// Compiles the project again on every change until it is stopped
+fn watch_project(project: PathBuf, output_path: PathBuf, nodes: Vec<PathBuf>, build: BuildConfig, output: &Output) -> ! {
+    // Without the disk cache, commands are still reused between the builds of this session
+    let cache: Arc<dyn CompileCache> = if build.no_cache.unwrap_or(false) {
+        Arc::new(MemoryCache::new(watch::CACHE_CAPACITY))
+    } else {
+        Arc::new(DiskCache::new(cache_dir(&project)))
+    };
+    let options = WatchOptions {
+        project,
+        output: output_path,
+        nodes,
+        force: build.force.unwrap_or(false),
+        cache,
+    };
+    if let Err(err) = watch(options, output) {
+        output.fail(EXIT_LOAD, "Failed to watch files".red().to_string(), &[err.to_string()], json!({}));
+    }
+    output.success("Stopped watching".to_string(), json!({}))
+}
+
+/// Loads nodes from all paths, exits if any of them can't be loaded
+fn load_nodes(paths: &[PathBuf], output: &Output) -> NodeRegistry {
+    match NodeRegistry::load(paths, &shared_engine()) {
+        Ok(nodes) => nodes,
+        Err(err) => output.fail(EXIT_LOAD, "Failed to load nodes".red().to_string(), &[err.to_pretty()], json!({})),
+    }
+}
+
+/// Loads project from a file, exits if it can't be loaded
+fn load_project(path: &std::path::Path, output: &Output) -> DisbotterProjectData {
+    match NodesJSCompiler::load_project(path.into()) {
+        Ok(project) => project,
+        Err(err) => output.fail(EXIT_LOAD, format!("{} {}", "Failed to load project:".red(), path.display().to_string().yellow()), &[err.to_pretty()], json!({})),
     }
 }
 
 fn main() {
     dotenv::dotenv().ok();
-    let cli = Cli::parse();
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(err) => {
+            if !wants_json() || matches!(err.kind(), clap::error::ErrorKind::DisplayHelp | clap::error::ErrorKind::DisplayVersion) {
+                err.exit();
+            }
+            Output::new(OutputFormat::Json).fail(EXIT_USAGE, err.kind().to_string(), &[err.to_string()], json!({}));
+        }
+    };
+    let output = Output::new(cli.format);
 
+    // Server sets up its own logger
+    if !matches!(cli.command, Some(Commands::Server { .. })) {
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    }
+
+    let (config_file, profile) = (cli.config, cli.profile);
     match cli.command {
-        Some(Commands::GenNodeDeclarations { path, output }) => {
+        Some(Commands::GenNodeDeclarations { path, output: output_path }) => {
             // If command is to generate node declarations
-            let paths = path.split(",");
-            let nodes: Vec<Node> = paths.flat_map(|path| load_all_nodes(path.into()).unwrap_or_else(|err| {
-                println!("{} {}", "Failed to load nodes from path:".red(), path.to_string().yellow());
-                println!("{:?}", err);
-                vec![]
-            })).collect();
-            export_node_declarations(nodes, output.clone().into());
-            println!("{} {}", "Successfully generated node declarations:".green(), output.yellow());
+            let config = load_config(config_file, profile, &output);
+            let paths = required(path.as_deref().map(split_paths).or(config.build.nodes), "--path", "build.nodes", &output);
+            let nodes = load_nodes(&paths, &output);
+            if let Err(err) = export_node_declarations(&nodes, output_path.clone().into()) {
+                output.fail(EXIT_EXPORT, format!("{} {}", "Failed to write node declarations:".red(), output_path.yellow()), &[err.to_string()], json!({}));
+            }
+            output.success(format!("{} {}", "Successfully generated node declarations:".green(), output_path.yellow()), json!({
+                "output": output_path,
+                "nodes": nodes.len(),
+            }));
+        },
+        Some(Commands::GenNodeDocs { path, output: output_path }) => {
+            // If command is to generate node documentation
+            let config = load_config(config_file, profile, &output);
+            let paths = required(path.as_deref().map(split_paths).or(config.build.nodes), "--path", "build.nodes", &output);
+            let nodes = load_nodes(&paths, &output);
+            let display = output_path.display().to_string();
+            match export_node_docs(&nodes, &output_path) {
+                Ok(written) => output.success(format!("{} {}", "Successfully generated node documentation:".green(), display.yellow()), json!({
+                    "output": display,
+                    "nodes": nodes.len(),
+                    "written": written,
+                })),
+                Err(err) => output.fail(EXIT_EXPORT, format!("{} {}", "Failed to write node documentation:".red(), display.yellow()), &[err.to_string()], json!({})),
+            }
         },
-        Some(Commands::Compile { path, output, nodes }) => {
+        Some(Commands::Compile { build: args, watch: false }) => {
             // If command is to compile a project
-            let project = NodesJSCompiler::load_project(path.clone().into());
+            let build = load_config(config_file, profile, &output).build.merge(args.into_config());
+            let path = required(build.project, "--path", "build.project", &output);
+            let output_path = required(build.output, "--output", "build.output", &output);
+            let nodes = required(build.nodes, "--nodes", "build.nodes", &output);
+            let force = build.force.unwrap_or(false);
+
+            let project = load_project(&path, &output);
             let mut compiler = NodesJSCompiler::new(project);
-            let paths = nodes.split(",");
-            for path in paths.into_iter() {
-                compiler.add_available_nodes(path.clone().into());
+            compiler.set_registry(Arc::new(load_nodes(&nodes, &output)));
+            if !build.no_cache.unwrap_or(false) {
+                compiler.set_cache(Arc::new(DiskCache::new(cache_dir(&path))));
+            }
+            let path = path.display().to_string();
+            // Generated code of packaged nodes imports their npm dependencies, the bot has to install them
+            let dependencies = npm_dependencies(compiler.registry().packages_used_by(compiler.project()));
+            // Errors in node actions are reported by the compilation itself
+            let warnings = check_project_flows(&compiler);
+            let errors = warnings.iter().filter(|d| d.is_error()).count();
+            if errors > 0 {
+                let details = warnings.iter().map(|d| d.to_pretty()).collect::<Vec<String>>();
+                output.fail(EXIT_COMPILE, format!("{} {} ({} errors, {} warnings)", "Project has errors:".red(), path.yellow(), errors, warnings.len() - errors), &details, json!({
+                    "diagnostics": warnings,
+                }));
+            }
+            for warning in warnings.iter() {
+                output.info(warning.to_pretty());
             }
-            let project = compiler.compile_project();
 
-            match project {
-                Ok(project) => {
-                    project.export_to_path(output.into());
-                    println!("{} {}", "Successfully compiled project:".green(), path.yellow());
+            let project = match compiler.compile_project() {
+                Ok(project) => project,
+                Err(err) => {
+                    output.fail(EXIT_COMPILE, format!("{} {}", "Failed to compile project:".red(), path.yellow()), &[err.to_pretty()], json!({}));
+                }
+            };
+
+            match project.export_to_path(output_path, force) {
+                Ok(report) => {
+                    for backed_up in report.backed_up.iter() {
+                        output.info(format!("{} {} -> {}.bak", "Backed up modified file:".yellow(), backed_up, backed_up));
+                    }
+                    for removed in report.removed.iter() {
+                        output.info(format!("{} {}", "Removed stale file:".yellow(), removed));
+                    }
+                    for (name, version) in dependencies.iter() {
+                        output.info(format!("{} {}@{}", "Required npm dependency:".cyan(), name, version));
+                    }
+                    output.success(format!("{} {}", "Successfully compiled project:".green(), path.yellow()), json!({
+                        "written": report.written,
+                        "removed": report.removed,
+                        "backedUp": report.backed_up,
+                        "npmDependencies": dependencies,
+                        "diagnostics": warnings,
+                    }));
                 },
                 Err(err) => {
-                    println!("{} {}", "Failed to compile project:".red(), path.yellow());
-                    println!("{}", err.to_pretty());
+                    output.fail(EXIT_EXPORT, format!("{} {}", "Failed to export project:".red(), path.yellow()), &[err.to_pretty()], json!({}));
                 }
             }
         },
+        Some(Commands::Compile { build: args, watch: true }) | Some(Commands::Watch { build: args }) => {
+            // If command is to compile a project on every change
+            let build = load_config(config_file, profile, &output).build.merge(args.into_config());
+            let path = required(build.project.clone(), "--path", "build.project", &output);
+            let output_path = required(build.output.clone(), "--output", "build.output", &output);
+            let nodes = required(build.nodes.clone(), "--nodes", "build.nodes", &output);
+            watch_project(path, output_path, nodes, build, &output);
+        },
+        Some(Commands::Check { path, nodes }) => {
+            // If command is to check a project
+            let build = load_config(config_file, profile, &output).build.merge(BuildConfig {
+                nodes: nodes.as_deref().map(split_paths),
+                project: path.map(PathBuf::from),
+                ..Default::default()
+            });
+            let path = required(build.project, "--path", "build.project", &output);
+            let nodes = required(build.nodes, "--nodes", "build.nodes", &output);
+
+            let project = load_project(&path, &output);
+            let mut compiler = NodesJSCompiler::new(project);
+            compiler.set_registry(Arc::new(load_nodes(&nodes, &output)));
+            let path = path.display().to_string();
+
+            let diagnostics = check_project(&compiler);
+            for diagnostic in diagnostics.iter() {
+                output.info(diagnostic.to_pretty());
+            }
+
+            let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+            let warnings = diagnostics.len() - errors;
+            let data = json!({
+                "errors": errors,
+                "warnings": warnings,
+                "diagnostics": diagnostics,
+            });
+            if errors > 0 {
+                output.fail(EXIT_COMPILE, format!("{} {} ({} errors, {} warnings)", "Project has errors:".red(), path.yellow(), errors, warnings), &[], data);
+            }
+            output.success(format!("{} {} ({} warnings)", "Project is valid:".green(), path.yellow(), warnings), data);
+        },
+        Some(Commands::Upgrade { paths }) => {
+            // If command is to upgrade project files
+            let mut results = vec![];
+            let mut failed = 0;
+            for path in paths.iter() {
+                let display = path.display().to_string();
+                match upgrade_project_file(path) {
+                    Ok(version) => {
+                        if version == FORMAT_VERSION {
+                            output.info(format!("{} {}", "Project is already up to date:".green(), display.yellow()));
+                        } else {
+                            output.info(format!("{} {} (v{} -> v{})", "Successfully upgraded project:".green(), display.yellow(), version, FORMAT_VERSION));
+                        }
+                        results.push(json!({ "path": display, "from": version, "to": FORMAT_VERSION }));
+                    },
+                    Err(err) => {
+                        output.info(format!("{} {}", "Failed to upgrade project:".red(), display.yellow()));
+                        output.info(err.to_pretty());
+                        results.push(json!({ "path": display, "error": err.to_pretty() }));
+                        failed += 1;
+                    }
+                }
+            }
+
+            let data = json!({ "projects": results });
+            if failed > 0 {
+                output.fail(EXIT_LOAD, format!("{} {} of {}", "Failed to upgrade projects:".red(), failed, paths.len()), &[], data);
+            }
+            output.success(format!("{} {}", "Successfully upgraded projects:".green(), paths.len()), data);
+        },
         Some(Commands::Init { path, pnpm }) => {
             // If command is to initialize a new project
             let url = "https://github.com/olix3001/disbotter-example-project";
-            println!("{} {}", "Cloning repository:".green(), url.yellow());
-            match git2::Repository::clone(url, path.clone()) {
-                Ok(_) => {},
-                Err(err) => {
-                    println!("{} {}", "Failed to clone repository:".red(), url.yellow());
-                    println!("{:?}", err);
-                    return;
-                }
-            };
+            output.info(format!("{} {}", "Cloning repository:".green(), url.yellow()));
+            if let Err(err) = git2::Repository::clone(url, path.clone()) {
+                output.fail(EXIT_FAILURE, format!("{} {}", "Failed to clone repository:".red(), url.yellow()), &[format!("{:?}", err)], json!({}));
+            }
 
             // Install dependencies
-            println!("{}", "Installing dependencies...".green());
+            output.info("Installing dependencies...".green().to_string());
             let mut cmd = if pnpm {
                 #[cfg(windows)]
                 let prog = "pnpm.cmd";
@@ -123,42 +479,98 @@ fn main() {
             };
 
             cmd.arg("install").current_dir(path.clone());
-            let output = cmd.output();
-
-            match output {
-                Ok(output) => {
-                    if output.status.success() {
-                        println!("{}", "Successfully installed dependencies:".green());
+            match cmd.output() {
+                Ok(result) => {
+                    if result.status.success() {
+                        output.info("Successfully installed dependencies:".green().to_string());
                     } else {
-                        println!("{}", "Failed to install dependencies:".red());
-                        println!("{}", String::from_utf8_lossy(&output.stderr));
-                        return;
+                        output.fail(EXIT_FAILURE, "Failed to install dependencies:".red().to_string(), &[String::from_utf8_lossy(&result.stderr).to_string()], json!({}));
                     }
                 },
                 Err(err) => {
-                    println!("{}", "Failed to install dependencies:".red());
-                    println!("{:?}", err);
-                    return;
+                    output.fail(EXIT_FAILURE, "Failed to install dependencies:".red().to_string(), &[format!("{:?}", err)], json!({}));
                 }
-            } 
+            }
 
             // Show success message
-            println!("{} {}", "Successfully initialized project in:".green(), path.yellow());
-
-            println!("{} {}", "To compile the project, run:".green(), format!("disbotter compile ... -o {}", std::path::PathBuf::from(path).join("src").to_str().unwrap()).yellow());
-            println!("{} {}", "To run the project, go to the project directory and run:".green(), "npm/pnpm start".yellow());
+            output.info(format!("{} {}", "To compile the project, run:".green(), format!("disbotter compile ... -o {}", std::path::PathBuf::from(&path).join("src").to_str().unwrap()).yellow()));
+            output.info(format!("{} {}", "To run the project, go to the project directory and run:".green(), "npm/pnpm start".yellow()));
+            output.success(format!("{} {}", "Successfully initialized project in:".green(), path.yellow()), json!({ "path": path }));
         },
-        Some(Commands::Server { nodes, projects }) => {
+        Some(Commands::Server { nodes, projects, host, port }) => {
             // If command is to start the server
-            
+            let config = load_config(config_file, profile, &output);
+            let server = config.server.merge(ServerConfig {
+                nodes: nodes.as_deref().map(split_paths),
+                projects,
+                host,
+                port,
+            });
+            let node_paths = required(server.nodes.or(config.build.nodes), "--nodes", "server.nodes", &output);
+
             let api = server::DisbotterRESTApi::new();
-            let mut engine = Engine::new();
-            upgrade_engine(&mut engine);
-            let nodes = AvailableNode::load_nodes(nodes.into(), &mut engine);
-            api.start(std::sync::Arc::new(nodes), projects).expect("Failed to start server");
+            let nodes = load_nodes(&node_paths, &output);
+            let host = server.host.unwrap_or(DEFAULT_HOST.to_string());
+            let port = server.port.unwrap_or(DEFAULT_PORT);
+            if let Err(err) = api.start(Arc::new(nodes), server.projects, (host.as_str(), port)) {
+                output.fail(EXIT_FAILURE, "Failed to start server".red().to_string(), &[err.to_string()], json!({}));
+            }
+        },
+        Some(Commands::Nodes { packages: args, command }) => {
+            // If command is to install or remove node packages
+            let packages = load_config(config_file, profile, &output).packages.merge(args.into_config());
+            let dir = packages.dir.unwrap_or(PathBuf::from(DEFAULT_DIR));
+            let source = || IndexSource::parse(&required(packages.index.clone(), "--index", "packages.index", &output));
+
+            match command {
+                NodesCommands::Add { package } => {
+                    let (name, requirement) = package.split_once('@').unwrap_or((&package, "*"));
+                    match market::add(&source(), &dir, name, requirement) {
+                        Ok(package) => output.success(format!("{} {} {}", "Installed package:".green(), package.name.yellow(), package.version), json!({
+                            "package": package,
+                        })),
+                        Err(err) => output.fail(EXIT_FAILURE, format!("{} {}", "Failed to install package:".red(), name.yellow()), &[err.to_pretty()], json!({})),
+                    }
+                },
+                NodesCommands::Remove { name } => {
+                    match market::remove(&dir, &name) {
+                        Ok(package) => output.success(format!("{} {} {}", "Removed package:".green(), package.name.yellow(), package.version), json!({
+                            "package": package,
+                        })),
+                        Err(err) => output.fail(EXIT_FAILURE, format!("{} {}", "Failed to remove package:".red(), name.yellow()), &[err.to_pretty()], json!({})),
+                    }
+                },
+                NodesCommands::Update { names } => {
+                    match market::update(&source(), &dir, &names) {
+                        Ok(updated) => {
+                            for (from, package) in updated.iter() {
+                                output.info(format!("{} {} {} -> {}", "Updated package:".cyan(), package.name.yellow(), from, package.version));
+                            }
+                            let updated = updated.into_iter().map(|(from, package)| json!({ "from": from, "package": package })).collect::<Vec<_>>();
+                            output.success(format!("{} ({} updated)", "Packages are up to date".green(), updated.len()), json!({
+                                "updated": updated,
+                            }));
+                        },
+                        Err(err) => output.fail(EXIT_FAILURE, "Failed to update packages".red().to_string(), &[err.to_pretty()], json!({})),
+                    }
+                }
+            }
+        },
+        Some(Commands::Config { command: ConfigCommands::Show { build, packages, projects, host, port } }) => {
+            // If command is to show the configuration, flags override it the same way as in the other commands
+            let config = load_config(config_file, profile, &output).merge(
+                build.into_config(),
+                ServerConfig { nodes: None, projects, host, port },
+                packages.into_config(),
+            );
+            if output.format == OutputFormat::Text {
+                print!("{}", toml::to_string_pretty(&config).unwrap());
+                return;
+            }
+            output.success("Merged configuration".to_string(), json!({ "config": config }));
         },
         None => {
-            println!("No command specified!");
+            output.fail(EXIT_USAGE, "No command specified!".to_string(), &[], json!({}));
         }
     }
-}
\ No newline at end of file
+}
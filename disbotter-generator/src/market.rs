@@ -0,0 +1,603 @@
+use std::{collections::{BTreeMap, BTreeSet}, io::Read, path::{Component, Path, PathBuf}};
+
+use colored::*;
+
+use crate::{cache::hash_bytes, loader::NodeScriptLoadingError, package::{Package, PACKAGE_MANIFEST}};
+
+/// Name of the index file in the root of the package index
+pub const INDEX_FILE: &str = "index.json";
+
+/// Name of the lockfile, it is kept in the directory the packages are installed to
+pub const LOCK_FILE: &str = "disbotter-nodes.lock";
+
+/// Directory the packages are installed to if none is given
+pub const DEFAULT_DIR: &str = "nodes";
+
+/// Contents of `index.json`, files of a package version are stored in `<name>/<version>/` next to it
+#[derive(Debug, serde::Deserialize)]
+pub struct Index {
+    /// Versions of every package by the package name
+    pub packages: BTreeMap<String, BTreeMap<String, IndexedVersion>>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct IndexedVersion {
+    /// Hashes of the files of the package by their path relative to the package directory
+    pub files: BTreeMap<String, String>,
+}
+
+/// Place the packages are installed from, either a local directory or an HTTP server with the same layout
+#[derive(Debug, Clone)]
+pub enum IndexSource {
+    Dir(PathBuf),
+    Http(String),
+}
+
+/// Installed package pinned to a version and the hashes of its files
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    /// Version requirement the package was added with, `update` stays within it
+    #[serde(default = "any_version")]
+    pub requirement: String,
+    /// Index the package was installed from
+    pub source: String,
+    pub files: BTreeMap<String, String>,
+}
+
+/// Contents of the lockfile.
+/// Nodes are loaded only if directories of the locked packages contain exactly the locked files
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Lockfile {
+    #[serde(rename = "package", default)]
+    pub packages: Vec<LockedPackage>,
+    /// Directory the packages are installed in
+    #[serde(skip)]
+    root: PathBuf,
+}
+
+/// Requirement of packages added without one, and of packages locked before requirements were stored
+fn any_version() -> String {
+    "*".to_string()
+}
+
+/// All possible errors that can occur while installing packages
+pub enum MarketError {
+    Io(PathBuf, std::io::Error),
+    /// File couldn't be fetched from the index, contains its location and the reason
+    Fetch(String, String),
+    InvalidIndex(String, String),
+    UnknownPackage(String),
+    InvalidRequirement(String, semver::Error),
+    NoMatchingVersion { name: String, requirement: String },
+    HashMismatch { file: String, expected: String, actual: String },
+    InvalidPackage { name: String, reason: String },
+    NotInstalled(String),
+    Loading(NodeScriptLoadingError),
+}
+
+impl MarketError {
+    pub fn to_pretty(&self) -> String {
+        match self {
+            MarketError::Io(path, err) => {
+                format!("{}: {} ({})", "IO error".red(), err, path.display())
+            },
+            MarketError::Fetch(location, reason) => {
+                format!("{}: {} ({})", "Failed to fetch".red(), reason, location)
+            },
+            MarketError::InvalidIndex(location, reason) => {
+                format!("{}: {} ({})", "Invalid package index".red(), reason, location)
+            },
+            MarketError::UnknownPackage(name) => {
+                format!("{}: {}", "Package not found in the index".red(), name)
+            },
+            MarketError::InvalidRequirement(requirement, err) => {
+                format!("{}: {} ({})", "Invalid version requirement".red(), requirement, err)
+            },
+            MarketError::NoMatchingVersion { name, requirement } => {
+                format!("{}: {} has no version matching {}", "No matching version".red(), name, requirement)
+            },
+            MarketError::HashMismatch { file, expected, actual } => {
+                format!("{}: {} (expected {}, got {})", "File doesn't match its hash".red(), file, expected, actual)
+            },
+            MarketError::InvalidPackage { name, reason } => {
+                format!("{}: {} ({})", "Invalid package".red(), reason, name)
+            },
+            MarketError::NotInstalled(name) => {
+                format!("{}: {}", "Package is not installed".red(), name)
+            },
+            MarketError::Loading(err) => err.to_pretty(),
+        }
+    }
+}
+
+impl std::fmt::Debug for MarketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_pretty())
+    }
+}
+
+impl From<NodeScriptLoadingError> for MarketError {
+    fn from(err: NodeScriptLoadingError) -> Self {
+        MarketError::Loading(err)
+    }
+}
+
+impl IndexSource {
+    /// Index given on the command line, URLs are fetched over HTTP and everything else is a directory
+    pub fn parse(source: &str) -> Self {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            IndexSource::Http(source.trim_end_matches('/').to_string())
+        } else {
+            IndexSource::Dir(PathBuf::from(source))
+        }
+    }
+
+    fn location(&self, file: &str) -> String {
+        match self {
+            IndexSource::Dir(dir) => dir.join(file).display().to_string(),
+            IndexSource::Http(url) => format!("{}/{}", url, file),
+        }
+    }
+
+    /// Reads file from the index, `file` is relative to the index root
+    fn fetch(&self, file: &str) -> Result<Vec<u8>, MarketError> {
+        match self {
+            IndexSource::Dir(dir) => {
+                let path = dir.join(file);
+                std::fs::read(&path).map_err(|err| MarketError::Io(path, err))
+            },
+            IndexSource::Http(_) => {
+                let url = self.location(file);
+                let response = ureq::get(&url).call().map_err(|err| {
+                    let reason = match err {
+                        ureq::Error::Status(status, _) => format!("server responded with {}", status),
+                        ureq::Error::Transport(err) => match std::error::Error::source(&err) {
+                            Some(source) => source.to_string(),
+                            None => err.kind().to_string(),
+                        },
+                    };
+                    MarketError::Fetch(url.clone(), reason)
+                })?;
+                let mut content = vec![];
+                response.into_reader().read_to_end(&mut content).map_err(|err| MarketError::Fetch(url, err.to_string()))?;
+                Ok(content)
+            }
+        }
+    }
+
+    pub fn index(&self) -> Result<Index, MarketError> {
+        let content = self.fetch(INDEX_FILE)?;
+        serde_json::from_slice(&content).map_err(|err| MarketError::InvalidIndex(self.location(INDEX_FILE), err.to_string()))
+    }
+}
+
+impl std::fmt::Display for IndexSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexSource::Dir(dir) => write!(f, "{}", dir.display()),
+            IndexSource::Http(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+impl Index {
+    /// Finds the newest version of the package matching the requirement
+    pub fn resolve(&self, name: &str, requirement: &str) -> Result<(semver::Version, &IndexedVersion), MarketError> {
+        let versions = self.packages.get(name).ok_or_else(|| MarketError::UnknownPackage(name.to_string()))?;
+        let parsed = semver::VersionReq::parse(requirement)
+            .map_err(|err| MarketError::InvalidRequirement(requirement.to_string(), err))?;
+
+        let mut newest: Option<(semver::Version, &IndexedVersion)> = None;
+        for (version, files) in versions.iter() {
+            let version = semver::Version::parse(version)
+                .map_err(|err| MarketError::InvalidIndex(name.to_string(), format!("invalid version \"{}\": {}", version, err)))?;
+            if parsed.matches(&version) && newest.as_ref().is_none_or(|(newest, _)| version > *newest) {
+                newest = Some((version, files));
+            }
+        }
+        newest.ok_or_else(|| MarketError::NoMatchingVersion { name: name.to_string(), requirement: requirement.to_string() })
+    }
+}
+
+impl Lockfile {
+    /// Reads lockfile from the directory, returns `None` if there is none
+    pub fn read(dir: &Path) -> Result<Option<Self>, NodeScriptLoadingError> {
+        let path = dir.join(LOCK_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let content = std::fs::read_to_string(&path).map_err(|err| NodeScriptLoadingError::Io(path.clone(), err))?;
+        let mut lockfile: Lockfile = toml::from_str(&content)
+            .map_err(|err| NodeScriptLoadingError::InvalidLockfile(path.clone(), err.to_string()))?;
+        lockfile.root = dir.to_path_buf();
+        Ok(Some(lockfile))
+    }
+
+    /// Reads lockfile from the directory, or creates an empty one
+    fn read_or_default(dir: &Path) -> Result<Self, MarketError> {
+        Ok(Self::read(dir)?.unwrap_or_else(|| Lockfile { packages: vec![], root: dir.to_path_buf() }))
+    }
+
+    fn write(&self) -> Result<(), MarketError> {
+        let path = self.root.join(LOCK_FILE);
+        let content = format!(
+            "# This file is generated by `disbotter nodes`, do not edit it by hand\n{}",
+            toml::to_string(self).expect("lockfile is always serializable")
+        );
+        std::fs::write(&path, content).map_err(|err| MarketError::Io(path, err))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LockedPackage> {
+        self.packages.iter().find(|package| package.name == name)
+    }
+
+    fn insert(&mut self, package: LockedPackage) {
+        self.packages.retain(|locked| locked.name != package.name);
+        self.packages.push(package);
+        self.packages.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    /// Checks that the directory of every locked package contains exactly the locked files with the locked hashes,
+    /// so modified, added and deleted files are found, including files that aren't scripts.
+    /// Files outside of the locked packages (like own nodes in the same directory) are not checked
+    pub fn verify(&self) -> Result<(), NodeScriptLoadingError> {
+        let mismatch = |file: PathBuf, reason: &str| NodeScriptLoadingError::LockMismatch {
+            file,
+            lockfile: self.root.join(LOCK_FILE),
+            reason: reason.to_string(),
+        };
+
+        for package in self.packages.iter() {
+            let dir = self.root.join(&package.name);
+            let mut found = BTreeSet::new();
+            for path in package_files(&dir)? {
+                let relative = path.strip_prefix(&dir).expect("files are listed from the package directory")
+                    .iter()
+                    .map(|c| c.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let content = std::fs::read(&path).map_err(|err| NodeScriptLoadingError::Io(path.clone(), err))?;
+                match package.files.get(&relative) {
+                    Some(expected) if *expected == hash_bytes(&content) => {},
+                    Some(_) => return Err(mismatch(path, "file was modified since the package was installed")),
+                    None => return Err(mismatch(path, "file is not part of the installed package")),
+                }
+                found.insert(relative);
+            }
+            if let Some(missing) = package.files.keys().find(|file| !found.contains(*file)) {
+                return Err(mismatch(dir.join(missing), "file of the installed package is missing"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lists all files in the package directory and its subdirectories, missing directory has no files
+fn package_files(dir: &Path) -> Result<Vec<PathBuf>, NodeScriptLoadingError> {
+    let mut files = vec![];
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    let entries = std::fs::read_dir(dir).map_err(|err| NodeScriptLoadingError::Io(dir.to_path_buf(), err))?;
+    for entry in entries {
+        let path = entry.map_err(|err| NodeScriptLoadingError::Io(dir.to_path_buf(), err))?.path();
+        if path.is_dir() {
+            files.extend(package_files(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Names of packages are used as directory names, so they have to be a single plain path component
+fn validate_name(name: &str) -> Result<(), MarketError> {
+    let mut components = Path::new(name).components();
+    let valid = matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\']);
+    if !valid {
+        return Err(MarketError::InvalidPackage { name: name.to_string(), reason: "package name has to be a plain directory name".to_string() });
+    }
+    Ok(())
+}
+
+/// Installs the newest version of the package matching the requirement (`*` for any version)
+pub fn add(source: &IndexSource, dir: &Path, name: &str, requirement: &str) -> Result<LockedPackage, MarketError> {
+    let index = source.index()?;
+    let (version, files) = index.resolve(name, requirement)?;
+    let mut lockfile = Lockfile::read_or_default(dir)?;
+    let package = install(source, dir, name, requirement, &version, files)?;
+    lockfile.insert(package.clone());
+    lockfile.write()?;
+    Ok(package)
+}
+
+/// Removes installed package, returns what was removed
+pub fn remove(dir: &Path, name: &str) -> Result<LockedPackage, MarketError> {
+    validate_name(name)?;
+    let mut lockfile = Lockfile::read_or_default(dir)?;
+    let package = lockfile.get(name).cloned().ok_or_else(|| MarketError::NotInstalled(name.to_string()))?;
+
+    let package_dir = dir.join(name);
+    if package_dir.exists() {
+        std::fs::remove_dir_all(&package_dir).map_err(|err| MarketError::Io(package_dir, err))?;
+    }
+    lockfile.packages.retain(|locked| locked.name != name);
+    lockfile.write()?;
+    Ok(package)
+}
+
+/// Updates the given packages (all installed packages if none are given) to the newest versions
+/// matching the requirements they were added with. Returns the packages that were updated together with their previous version
+pub fn update(source: &IndexSource, dir: &Path, names: &[String]) -> Result<Vec<(String, LockedPackage)>, MarketError> {
+    let mut lockfile = Lockfile::read_or_default(dir)?;
+    for name in names {
+        if lockfile.get(name).is_none() {
+            return Err(MarketError::NotInstalled(name.clone()));
+        }
+    }
+
+    let index = source.index()?;
+    let mut updated = vec![];
+    for locked in lockfile.packages.clone() {
+        if !names.is_empty() && !names.contains(&locked.name) {
+            continue;
+        }
+        let (version, files) = index.resolve(&locked.name, &locked.requirement)?;
+        if locked.version == version.to_string() && locked.files == files.files {
+            continue;
+        }
+        let package = install(source, dir, &locked.name, &locked.requirement, &version, files)?;
+        lockfile.insert(package.clone());
+        updated.push((locked.version, package));
+    }
+    lockfile.write()?;
+    Ok(updated)
+}
+
+/// Downloads the package into `dir/name`, all files are verified before anything is replaced
+fn install(source: &IndexSource, dir: &Path, name: &str, requirement: &str, version: &semver::Version, indexed: &IndexedVersion) -> Result<LockedPackage, MarketError> {
+    // Names come from the index, which isn't trusted more than the files in it
+    validate_name(name)?;
+    // Files are downloaded next to the package first, so a failed download leaves the installed version intact
+    let staging = dir.join(format!(".{}.partial", name));
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging).map_err(|err| MarketError::Io(staging.clone(), err))?;
+    }
+    if let Err(err) = download(source, &staging, name, version, indexed) {
+        // Scripts left in the staging directory would be loaded as nodes
+        std::fs::remove_dir_all(&staging).ok();
+        return Err(err);
+    }
+
+    let target = dir.join(name);
+    if target.exists() {
+        std::fs::remove_dir_all(&target).map_err(|err| MarketError::Io(target.clone(), err))?;
+    }
+    std::fs::rename(&staging, &target).map_err(|err| MarketError::Io(target, err))?;
+
+    Ok(LockedPackage {
+        name: name.to_string(),
+        version: version.to_string(),
+        requirement: requirement.to_string(),
+        source: source.to_string(),
+        files: indexed.files.clone(),
+    })
+}
+
+/// Downloads and verifies all files of the package version into the directory
+fn download(source: &IndexSource, target: &Path, name: &str, version: &semver::Version, indexed: &IndexedVersion) -> Result<(), MarketError> {
+    let invalid = |reason: String| MarketError::InvalidPackage { name: name.to_string(), reason };
+    if !indexed.files.contains_key(PACKAGE_MANIFEST) {
+        return Err(invalid(format!("package has no {}", PACKAGE_MANIFEST)));
+    }
+
+    for (file, expected) in indexed.files.iter() {
+        if file.is_empty() || !Path::new(file).components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(invalid(format!("file path \"{}\" leaves the package directory", file)));
+        }
+        let location = format!("{}/{}/{}", name, version, file);
+        let content = source.fetch(&location)?;
+        let actual = hash_bytes(&content);
+        if actual != *expected {
+            return Err(MarketError::HashMismatch { file: source.location(&location), expected: expected.clone(), actual });
+        }
+
+        let path = target.join(file);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| MarketError::Io(parent.to_path_buf(), err))?;
+        }
+        std::fs::write(&path, content).map_err(|err| MarketError::Io(path, err))?;
+    }
+
+    let package = Package::read(target)?.expect("manifest was just written");
+    if package.name != name || package.version != version.to_string() {
+        return Err(invalid(format!("manifest declares {} {} instead of {} {}", package.name, package.version, name, version)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compiler::shared_engine, registry::NodeRegistry};
+
+    use super::*;
+
+    const SCRIPT: &str = "const id = \"hello\";\nconst title = \"Hello\";\nconst description = \"\";\nconst category = \"Test\";\nconst inputs = #{};\nconst outputs = #{};\nfn action(builder) {}\n";
+
+    /// Package index with versions 1.0.0, 1.2.0 and 2.0.0 of package `greet`
+    fn index() -> (tempfile::TempDir, IndexSource) {
+        let dir = tempfile::tempdir().unwrap();
+        for version in ["1.0.0", "1.2.0", "2.0.0"] {
+            publish(dir.path(), version);
+        }
+
+        let source = IndexSource::Dir(dir.path().to_path_buf());
+        (dir, source)
+    }
+
+    /// Adds version of package `greet` to the index in the directory
+    fn publish(dir: &Path, version: &str) {
+        let index_path = dir.join(INDEX_FILE);
+        let mut index = match index_path.is_file() {
+            true => serde_json::from_str(&std::fs::read_to_string(&index_path).unwrap()).unwrap(),
+            false => serde_json::json!({ "packages": { "greet": {} } }),
+        };
+
+        let manifest = format!("name = \"greet\"\nversion = \"{}\"\nauthor = \"someone\"\n", version);
+        let mut files = serde_json::Map::new();
+        for (file, content) in [(PACKAGE_MANIFEST, manifest.as_str()), ("nodes/hello.rhai", SCRIPT)] {
+            let path = dir.join("greet").join(version).join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, content).unwrap();
+            files.insert(file.to_string(), hash_bytes(content.as_bytes()).into());
+        }
+        index["packages"]["greet"][version] = serde_json::json!({ "files": files });
+        std::fs::write(&index_path, index.to_string()).unwrap();
+    }
+
+    #[test]
+    fn resolves_newest_matching_version() {
+        let (_dir, source) = index();
+        let index = source.index().unwrap();
+
+        assert_eq!(index.resolve("greet", "*").unwrap().0.to_string(), "2.0.0");
+        assert_eq!(index.resolve("greet", "^1").unwrap().0.to_string(), "1.2.0");
+        assert_eq!(index.resolve("greet", "=1.0.0").unwrap().0.to_string(), "1.0.0");
+        assert!(matches!(index.resolve("greet", "^3"), Err(MarketError::NoMatchingVersion { .. })));
+        assert!(matches!(index.resolve("greet", "one"), Err(MarketError::InvalidRequirement(..))));
+        assert!(matches!(index.resolve("wave", "*"), Err(MarketError::UnknownPackage(name)) if name == "wave"));
+    }
+
+    #[test]
+    fn installs_and_locks_package() {
+        let (_index_dir, source) = index();
+        let dir = tempfile::tempdir().unwrap();
+
+        let package = add(&source, dir.path(), "greet", "^1").unwrap();
+        assert_eq!(package.version, "1.2.0");
+        assert!(dir.path().join("greet/nodes/hello.rhai").is_file());
+
+        let lockfile = Lockfile::read(dir.path()).unwrap().unwrap();
+        assert_eq!(lockfile.get("greet").unwrap().version, "1.2.0");
+        assert_eq!(lockfile.get("greet").unwrap().files, package.files);
+        lockfile.verify().unwrap();
+
+        let registry = NodeRegistry::load(&[dir.path().to_path_buf()], &shared_engine()).unwrap();
+        assert!(registry.get("greet/hello").is_some());
+
+        assert!(update(&source, dir.path(), &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn updates_within_the_requirement() {
+        let (index_dir, source) = index();
+        let dir = tempfile::tempdir().unwrap();
+        add(&source, dir.path(), "greet", "^1").unwrap();
+        assert_eq!(Lockfile::read(dir.path()).unwrap().unwrap().get("greet").unwrap().requirement, "^1");
+
+        // 2.0.0 is newer, but doesn't match the requirement
+        assert!(update(&source, dir.path(), &[]).unwrap().is_empty());
+
+        publish(index_dir.path(), "1.3.0");
+        let updated = update(&source, dir.path(), &["greet".to_string()]).unwrap();
+        assert_eq!(updated.len(), 1);
+        assert_eq!(updated[0].0, "1.2.0");
+        let locked = Lockfile::read(dir.path()).unwrap().unwrap().get("greet").unwrap().clone();
+        assert_eq!((locked.version.as_str(), locked.requirement.as_str()), ("1.3.0", "^1"));
+        Lockfile::read(dir.path()).unwrap().unwrap().verify().unwrap();
+
+        // Adding the package again changes the requirement
+        add(&source, dir.path(), "greet", "*").unwrap();
+        publish(index_dir.path(), "2.1.0");
+        assert_eq!(update(&source, dir.path(), &[]).unwrap()[0].1.version, "2.1.0");
+    }
+
+    #[test]
+    fn lockfiles_without_requirements_allow_any_version() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(LOCK_FILE), "[[package]]\nname = \"greet\"\nversion = \"1.0.0\"\nsource = \"index\"\nfiles = {}\n").unwrap();
+        assert_eq!(Lockfile::read(dir.path()).unwrap().unwrap().get("greet").unwrap().requirement, "*");
+    }
+
+    #[test]
+    fn rejects_files_that_dont_match_the_index() {
+        let (index_dir, source) = index();
+        std::fs::write(index_dir.path().join("greet/2.0.0/nodes/hello.rhai"), "modified").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(matches!(add(&source, dir.path(), "greet", "*"), Err(MarketError::HashMismatch { .. })));
+        assert!(!dir.path().join("greet").exists());
+        assert!(!dir.path().join(".greet.partial").exists());
+        assert!(Lockfile::read(dir.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn verify_finds_changed_files() {
+        let (_index_dir, source) = index();
+        let dir = tempfile::tempdir().unwrap();
+        add(&source, dir.path(), "greet", "*").unwrap();
+        let package_dir = dir.path().join("greet");
+
+        let assert_mismatch = |file: PathBuf, expected: &str| {
+            let lockfile = Lockfile::read(dir.path()).unwrap().unwrap();
+            match lockfile.verify() {
+                Err(NodeScriptLoadingError::LockMismatch { file: actual, reason, .. }) => {
+                    assert_eq!(actual, file);
+                    assert!(reason.contains(expected), "{}", reason);
+                },
+                other => panic!("expected lock mismatch, got {:?}", other),
+            }
+            assert!(NodeRegistry::load(&[dir.path().to_path_buf()], &shared_engine()).is_err());
+        };
+
+        // Own nodes next to the packages are not checked
+        std::fs::write(dir.path().join("own.rhai"), SCRIPT.replace("hello", "own")).unwrap();
+        Lockfile::read(dir.path()).unwrap().unwrap().verify().unwrap();
+
+        let script = package_dir.join("nodes/hello.rhai");
+        std::fs::write(&script, SCRIPT.replace("Hello", "Hi")).unwrap();
+        assert_mismatch(script.clone(), "modified");
+        std::fs::write(&script, SCRIPT).unwrap();
+
+        let extra = package_dir.join("notes.txt");
+        std::fs::write(&extra, "").unwrap();
+        assert_mismatch(extra.clone(), "not part");
+        std::fs::remove_file(&extra).unwrap();
+
+        let manifest = package_dir.join(PACKAGE_MANIFEST);
+        std::fs::remove_file(&manifest).unwrap();
+        assert_mismatch(manifest, "missing");
+    }
+
+    #[test]
+    fn removes_package() {
+        let (_index_dir, source) = index();
+        let dir = tempfile::tempdir().unwrap();
+        add(&source, dir.path(), "greet", "*").unwrap();
+
+        assert_eq!(remove(dir.path(), "greet").unwrap().version, "2.0.0");
+        assert!(!dir.path().join("greet").exists());
+        assert!(Lockfile::read(dir.path()).unwrap().unwrap().packages.is_empty());
+        assert!(matches!(remove(dir.path(), "greet"), Err(MarketError::NotInstalled(_))));
+    }
+
+    #[test]
+    fn rejects_names_outside_of_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let packages = dir.path().join("nodes");
+        std::fs::create_dir(&packages).unwrap();
+        std::fs::write(packages.join(LOCK_FILE), "[[package]]\nname = \"..\"\nversion = \"1.0.0\"\nsource = \"index\"\nfiles = {}\n").unwrap();
+
+        for name in ["..", ".", "", "a/b", "../nodes", ".hidden", "/abs"] {
+            assert!(matches!(remove(&packages, name), Err(MarketError::InvalidPackage { .. })), "{}", name);
+        }
+        assert!(packages.join(LOCK_FILE).is_file());
+    }
+}
@@ -0,0 +1,131 @@
+use serde_json::{json, Value};
+
+/// Exit code for errors that don't fit any other category
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit code for invalid command line arguments
+pub const EXIT_USAGE: i32 = 2;
+/// Exit code for projects or nodes that couldn't be loaded
+pub const EXIT_LOAD: i32 = 3;
+/// Exit code for projects that failed to compile or check
+pub const EXIT_COMPILE: i32 = 4;
+/// Exit code for output that couldn't be written
+pub const EXIT_EXPORT: i32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Colored text for people
+    Text,
+    /// Single JSON object for scripts
+    Json,
+}
+
+/// Prints results of the CLI commands in the selected format.
+/// In JSON format nothing but the final result is printed to stdout,
+/// it always has `success` and `message` fields, failed commands also have an `error` field
+pub struct Output {
+    pub format: OutputFormat,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Self {
+        if format == OutputFormat::Json {
+            // Messages end up in JSON, so they can't contain color codes
+            colored::control::set_override(false);
+        }
+        Self { format }
+    }
+
+    /// Prints a progress message, it is shown only in text format
+    pub fn info(&self, message: String) {
+        if self.format == OutputFormat::Text {
+            println!("{}", message);
+        }
+    }
+
+    /// Prints result of a successful command and exits.
+    /// `data` is an object with additional fields of the JSON result
+    pub fn success(&self, message: String, data: Value) -> ! {
+        self.event(None, message, &[], data);
+        std::process::exit(0)
+    }
+
+    /// Prints error and exits with the given code.
+    /// `details` are additional lines explaining the error, `data` is an object with additional fields of the JSON result
+    pub fn fail(&self, code: i32, message: String, details: &[String], data: Value) -> ! {
+        self.event(Some(code), message, details, data);
+        std::process::exit(code)
+    }
+
+    /// Prints result without exiting, used by commands that produce many results (like watch mode).
+    /// `code` is the exit code the result would have if it was the last one, `None` for success.
+    /// In JSON format every result is printed as a separate line
+    pub fn event(&self, code: Option<i32>, message: String, details: &[String], data: Value) {
+        match (self.format, code) {
+            (OutputFormat::Text, _) => {
+                println!("{}", message);
+                for detail in details {
+                    println!("{}", detail);
+                }
+            },
+            (OutputFormat::Json, code) => println!("{}", json_result(code, message, details, data)),
+        }
+    }
+}
+
+/// Builds the JSON result, fields of `data` are added next to the common ones
+fn json_result(code: Option<i32>, message: String, details: &[String], data: Value) -> Value {
+    let mut result = match code {
+        None => json!({ "success": true, "message": message }),
+        Some(code) => json!({
+            "success": false,
+            "message": message,
+            "error": {
+                "kind": error_kind(code),
+                "code": code,
+                "details": details,
+            },
+        }),
+    };
+    if let (Some(result), Value::Object(data)) = (result.as_object_mut(), data) {
+        result.extend(data);
+    }
+    result
+}
+
+fn error_kind(code: i32) -> &'static str {
+    match code {
+        EXIT_USAGE => "usage",
+        EXIT_LOAD => "load",
+        EXIT_COMPILE => "compile",
+        EXIT_EXPORT => "export",
+        _ => "failure",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn successful_result_has_data() {
+        let result = json_result(None, "Compiled".to_string(), &[], json!({ "written": ["a.ts"] }));
+        assert_eq!(result, json!({ "success": true, "message": "Compiled", "written": ["a.ts"] }));
+    }
+
+    #[test]
+    fn failed_result_has_error() {
+        let result = json_result(Some(EXIT_COMPILE), "Project has errors".to_string(), &["n1: bad".to_string()], json!({ "diagnostics": [] }));
+        assert_eq!(result, json!({
+            "success": false,
+            "message": "Project has errors",
+            "error": { "kind": "compile", "code": EXIT_COMPILE, "details": ["n1: bad"] },
+            "diagnostics": [],
+        }));
+    }
+
+    #[test]
+    fn error_kinds_match_exit_codes() {
+        let kinds = [EXIT_FAILURE, EXIT_USAGE, EXIT_LOAD, EXIT_COMPILE, EXIT_EXPORT].map(error_kind);
+        assert_eq!(kinds, ["failure", "usage", "load", "compile", "export"]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}, sync::Arc};
+
+use crate::loader::NodeScriptLoadingError;
+
+/// Name of the manifest file, directory containing it is a node package
+pub const PACKAGE_MANIFEST: &str = "disbotter-package.toml";
+
+/// Contents of the package manifest
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct PackageManifest {
+    name: String,
+    version: String,
+    author: String,
+    #[serde(default)]
+    description: Option<String>,
+    /// Oldest generator version the nodes of the package work with
+    #[serde(default)]
+    min_generator_version: Option<String>,
+    /// Packages the generated code of the nodes imports, by their version requirement
+    #[serde(default)]
+    npm_dependencies: BTreeMap<String, String>,
+}
+
+/// Node package, ids of its nodes are prefixed with the package name (`package/node`).
+/// It is included in the node declarations, so the editor can group nodes by their package
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub npm_dependencies: BTreeMap<String, String>,
+}
+
+impl Package {
+    /// Reads package from the manifest in the directory, returns `None` if the directory isn't a package
+    pub fn read(dir: &Path) -> Result<Option<Arc<Package>>, NodeScriptLoadingError> {
+        let path = dir.join(PACKAGE_MANIFEST);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let content = std::fs::read_to_string(&path).map_err(|err| NodeScriptLoadingError::Io(path.clone(), err))?;
+        let manifest: PackageManifest = toml::from_str(&content)
+            .map_err(|err| NodeScriptLoadingError::InvalidManifest(path.clone(), err.to_string()))?;
+        manifest.validate(&path).map(|package| Some(Arc::new(package)))
+    }
+
+    /// Finds package the script belongs to, the closest manifest between the script and `root` is used
+    pub fn find(script: &Path, root: &Path) -> Result<Option<Arc<Package>>, NodeScriptLoadingError> {
+        for dir in script.ancestors().skip(1) {
+            if let Some(package) = Self::read(dir)? {
+                return Ok(Some(package));
+            }
+            if dir == root {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Gets id of a node of this package
+    pub fn node_id(&self, id: &str) -> String {
+        format!("{}/{}", self.name, id)
+    }
+}
+
+impl PackageManifest {
+    fn validate(self, path: &Path) -> Result<Package, NodeScriptLoadingError> {
+        let invalid = |message: String| NodeScriptLoadingError::InvalidManifest(path.to_path_buf(), message);
+
+        if self.name.is_empty() || !self.name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_') {
+            return Err(invalid(format!("package name \"{}\" can contain only lowercase letters, digits, - and _", self.name)));
+        }
+        semver::Version::parse(&self.version)
+            .map_err(|err| invalid(format!("version \"{}\" is not a valid semantic version: {}", self.version, err)))?;
+
+        if let Some(required) = &self.min_generator_version {
+            let required = semver::Version::parse(required)
+                .map_err(|err| invalid(format!("min-generator-version \"{}\" is not a valid semantic version: {}", required, err)))?;
+            let current = semver::Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+            if current < required {
+                return Err(NodeScriptLoadingError::IncompatiblePackage { manifest: path.to_path_buf(), name: self.name, required });
+            }
+        }
+
+        Ok(Package {
+            name: self.name,
+            version: self.version,
+            author: self.author,
+            description: self.description,
+            npm_dependencies: self.npm_dependencies,
+        })
+    }
+}
+
+/// Collects npm dependencies of all packages, when packages require the same dependency the first requirement is kept
+pub fn npm_dependencies<'a>(packages: impl IntoIterator<Item = &'a Package>) -> BTreeMap<String, String> {
+    let mut dependencies = BTreeMap::new();
+    for package in packages {
+        for (name, version) in package.npm_dependencies.iter() {
+            dependencies.entry(name.clone()).or_insert_with(|| version.clone());
+        }
+    }
+    dependencies
+}
+
+/// Gets directory of the package the manifest belongs to
+pub fn manifest_dir(manifest: &Path) -> Option<PathBuf> {
+    if manifest.file_name()? == PACKAGE_MANIFEST {
+        manifest.parent().map(Path::to_path_buf)
+    } else {
+        None
+    }
+}
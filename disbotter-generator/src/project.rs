@@ -0,0 +1,596 @@
+use std::{collections::{BTreeMap, HashMap}, path::{Path, PathBuf}, sync::Arc};
+
+use colored::*;
+use serde::Serialize;
+use serde_json::{ser::PrettyFormatter, Map, Value};
+
+use crate::{compiler::PortIdentifier, loader::variadic_key};
+
+/// Version of the project format written by the current editor.
+/// Bump it together with adding a migration to `MIGRATIONS` whenever the format changes
+pub const FORMAT_VERSION: u64 = 2;
+
+/// Type of the node every command flow starts from, the editor adds it to every command
+pub const START_NODE: &str = "__start__";
+
+/// Migration upgrading project JSON from one version to the next one
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// Migrations in order, migration at index `i` upgrades version `i + 1` to `i + 2`
+const MIGRATIONS: &[Migration] = &[
+    migrate_v1_to_v2,
+];
+
+/// All possible errors that can occur while loading a project
+pub enum ProjectLoadError {
+    Io(PathBuf, std::io::Error),
+    InvalidJson(serde_json::Error),
+    /// Project was saved by a newer version of the editor
+    UnsupportedVersion(u64),
+    /// Format versions start at 1
+    InvalidVersion(u64),
+    Migration { from: u64, message: String },
+}
+
+impl ProjectLoadError {
+    pub fn to_pretty(&self) -> String {
+        match self {
+            ProjectLoadError::Io(path, err) => {
+                format!("{}: {} ({})", "IO error".red(), err, path.display())
+            },
+            ProjectLoadError::InvalidJson(err) => {
+                format!("{}: {}", "Invalid project file".red(), err)
+            },
+            ProjectLoadError::UnsupportedVersion(version) => {
+                format!("{}: project uses format version {}, but the newest supported version is {}", "Unsupported project version".red(), version, FORMAT_VERSION)
+            },
+            ProjectLoadError::InvalidVersion(version) => {
+                format!("{}: {}", "Invalid project version".red(), version)
+            },
+            ProjectLoadError::Migration { from, message } => {
+                format!("{}: {}", format!("Failed to upgrade project from version {}", from).red(), message)
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ProjectLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_pretty())
+    }
+}
+
+/// Returns format version of the project JSON, projects from before versioning are version 1
+pub fn format_version(project: &Value) -> u64 {
+    project.pointer("/metadata/formatVersion")
+        .and_then(Value::as_u64)
+        .unwrap_or(1)
+}
+
+/// Upgrades project JSON to the current format version step by step.
+/// Returns version the project had before the upgrade
+pub fn migrate(project: &mut Value) -> Result<u64, ProjectLoadError> {
+    let original = format_version(project);
+    if original < 1 {
+        return Err(ProjectLoadError::InvalidVersion(original));
+    }
+    if original > FORMAT_VERSION {
+        return Err(ProjectLoadError::UnsupportedVersion(original));
+    }
+
+    for version in original..FORMAT_VERSION {
+        let migration = MIGRATIONS[(version - 1) as usize];
+        migration(project).map_err(|message| ProjectLoadError::Migration { from: version, message })?;
+        set_format_version(project, version + 1)?;
+    }
+
+    Ok(original)
+}
+
+/// Upgrades project JSON and converts it to project data
+pub fn parse_project(mut project: Value) -> Result<DisbotterProjectData, ProjectLoadError> {
+    migrate(&mut project)?;
+    from_migrated(project)
+}
+
+/// Converts project JSON that is already in the current format to project data
+fn from_migrated(project: Value) -> Result<DisbotterProjectData, ProjectLoadError> {
+    let mut data: DisbotterProjectData = serde_json::from_value(project.clone()).map_err(ProjectLoadError::InvalidJson)?;
+    data.source = Some(project);
+    Ok(data)
+}
+
+/// Reads project JSON from a file without upgrading it, together with the formatting of the file
+pub fn read_project(path: &Path) -> Result<(Value, JsonFormat), ProjectLoadError> {
+    let file = std::fs::read_to_string(path).map_err(|err| ProjectLoadError::Io(path.to_path_buf(), err))?;
+    let project = serde_json::from_str(&file).map_err(ProjectLoadError::InvalidJson)?;
+    Ok((project, JsonFormat::detect(&file)))
+}
+
+/// Loads project from a file, upgrading it to the current format version
+pub fn load_project(path: &Path) -> Result<DisbotterProjectData, ProjectLoadError> {
+    let (project, format) = read_project(path)?;
+    let mut data = parse_project(project)?;
+    data.format = format;
+    Ok(data)
+}
+
+/// Upgrades project file in place, keeping its formatting.
+/// Returns version the project had before the upgrade, file is not touched if it was already up to date
+pub fn upgrade_project_file(path: &Path) -> Result<u64, ProjectLoadError> {
+    let (mut project, format) = read_project(path)?;
+    let original = migrate(&mut project)?;
+
+    if original < FORMAT_VERSION {
+        // Going through project data makes sure the upgraded project can actually be loaded before overwriting the file
+        let mut project = from_migrated(project)?;
+        project.format = format;
+        std::fs::write(path, project.to_json_string()).map_err(|err| ProjectLoadError::Io(path.to_path_buf(), err))?;
+    }
+
+    Ok(original)
+}
+
+fn set_format_version(project: &mut Value, version: u64) -> Result<(), ProjectLoadError> {
+    match project.get_mut("metadata").and_then(Value::as_object_mut) {
+        Some(metadata) => {
+            metadata.insert("formatVersion".to_string(), Value::from(version));
+            Ok(())
+        },
+        None => Err(ProjectLoadError::Migration { from: version - 1, message: "Project has no metadata".to_string() })
+    }
+}
+
+// ===< Data structures >=== //
+// Every structure keeps fields it doesn't know about (like node positions) in `extra`,
+// so projects can be loaded, modified and saved without losing editor data
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DisbotterProjectData {
+    pub metadata: DisbotterProjectMetadata,
+    pub content: DisbotterProjectContent,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+    /// JSON the project was loaded from, used to keep the original key order when saving
+    #[serde(skip)]
+    pub source: Option<Value>,
+    /// Formatting of the file the project was loaded from, projects from the editor are compact
+    #[serde(skip)]
+    pub format: JsonFormat,
+}
+
+impl DisbotterProjectData {
+    /// Serializes the project with the key order and formatting of the file it was loaded from.
+    /// Only indentation, line endings and the trailing newline are kept, other whitespace is normalized,
+    /// so an unmodified file is written back unchanged only if it was formatted consistently (as the editor does)
+    pub fn to_json_string(&self) -> String {
+        let mut value = serde_json::to_value(self).unwrap();
+        if let Some(source) = &self.source {
+            restore_key_order(&mut value, source);
+        }
+        self.format.write(&value)
+    }
+}
+
+/// Whitespace style of a project file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JsonFormat {
+    /// Indentation of one nesting level, compact JSON has none
+    indent: Option<String>,
+    crlf: bool,
+    trailing_newline: bool,
+}
+
+impl JsonFormat {
+    /// Detects the style of the JSON text, indentation is taken from the first indented line
+    pub fn detect(text: &str) -> Self {
+        let indent = text.trim_end()
+            .split_once('\n')
+            .map(|(_, rest)| rest.chars().take_while(|c| *c == ' ' || *c == '\t').collect::<String>())
+            .filter(|indent| !indent.is_empty());
+
+        Self {
+            indent,
+            crlf: text.contains("\r\n"),
+            trailing_newline: text.ends_with('\n'),
+        }
+    }
+
+    fn write(&self, value: &Value) -> String {
+        let mut text = match &self.indent {
+            Some(indent) => {
+                let mut out = vec![];
+                let mut serializer = serde_json::Serializer::with_formatter(&mut out, PrettyFormatter::with_indent(indent.as_bytes()));
+                value.serialize(&mut serializer).unwrap();
+                String::from_utf8(out).unwrap()
+            },
+            None => serde_json::to_string(value).unwrap(),
+        };
+
+        if self.trailing_newline {
+            text.push('\n');
+        }
+        // JSON strings can't contain raw newlines, so all of them are formatting
+        if self.crlf {
+            text = text.replace('\n', "\r\n");
+        }
+        text
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DisbotterProjectMetadata {
+    pub name: String,
+    #[serde(rename = "formatVersion")]
+    pub format_version: u64,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DisbotterProjectContent {
+    pub commands: Vec<DisbotterProjectCommand>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct DisbotterProjectCommand {
+    pub uid: String,
+    pub name: String,
+    pub description: String,
+    /// Shared with the graph built when the command is compiled, so the flow is never copied
+    pub flow: Arc<DisbotterFlow>,
+    pub options: Vec<DisbotterProjectCommandOption>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct DisbotterProjectCommandOption {
+    pub name: String,
+    pub description: String,
+    #[serde(rename = "type")]
+    pub option_type: i32,
+    pub required: bool,
+    pub choices: Vec<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct DisbotterFlow {
+    pub nodes: Vec<DisbotterFlowNode>,
+    pub connections: Vec<DisbotterFlowConnection>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct DisbotterFlowNode {
+    pub uid: String,
+    #[serde(rename = "type")]
+    pub node_type: String,
+    #[serde(rename = "inputHardcoded")]
+    pub input_hardcoded: Map<String, Value>,
+    /// Number of instances of the variadic inputs by their keys.
+    /// Nodes without variadic inputs usually don't have it, an empty one is kept as it is
+    #[serde(rename = "inputCounts", skip_serializing_if = "Option::is_none", default)]
+    pub input_counts: Option<BTreeMap<String, usize>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl DisbotterFlowNode {
+    /// Keys of all instances of the variadic input, in the order they are in the editor
+    pub fn variadic_keys(&self, key: &str) -> Vec<String> {
+        (0..self.input_count(key)).map(|index| variadic_key(key, index)).collect()
+    }
+
+    /// Number of instances of the variadic input
+    pub fn input_count(&self, key: &str) -> usize {
+        self.input_counts.as_ref()
+            .and_then(|counts| counts.get(key))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Gets value hardcoded in the editor for the given input
+    pub fn get_hardcoded(&self, key: &str) -> Option<&Value> {
+        self.input_hardcoded.get(key)
+    }
+
+    pub fn get_port_in(&self, key: &str) -> PortIdentifier {
+        PortIdentifier::Input {
+            node_uid: self.uid.clone(),
+            port_key: key.to_string()
+        }
+    }
+
+    pub fn get_port_out(&self, key: &str) -> PortIdentifier {
+        PortIdentifier::Output {
+            node_uid: self.uid.clone(),
+            port_key: key.to_string()
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct DisbotterFlowConnection {
+    #[serde(rename = "type")]
+    pub connection_type: i32,
+    pub from: String,
+    pub to: String,
+    #[serde(rename = "fromKey")]
+    pub from_key: String,
+    #[serde(rename = "toKey")]
+    pub to_key: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Reorders keys of all objects in `value` to match the order in `original`.
+/// Keys that are not in the original are placed after the others.
+/// Array items are matched by `uid` if they have one, otherwise by position
+fn restore_key_order(value: &mut Value, original: &Value) {
+    match (value, original) {
+        (Value::Object(map), Value::Object(original)) => {
+            let positions = original.keys()
+                .enumerate()
+                .map(|(i, key)| (key.as_str(), i))
+                .collect::<HashMap<&str, usize>>();
+
+            let mut entries = std::mem::take(map).into_iter().collect::<Vec<(String, Value)>>();
+            entries.sort_by_key(|(key, _)| positions.get(key.as_str()).copied().unwrap_or(usize::MAX));
+
+            for (key, mut item) in entries {
+                if let Some(original_item) = original.get(&key) {
+                    restore_key_order(&mut item, original_item);
+                }
+                map.insert(key, item);
+            }
+        },
+        (Value::Array(items), Value::Array(original)) => {
+            let by_uid = original.iter()
+                .filter_map(|item| Some((item.get("uid")?.as_str()?, item)))
+                .collect::<HashMap<&str, &Value>>();
+
+            for (i, item) in items.iter_mut().enumerate() {
+                let original_item = match item.get("uid").and_then(Value::as_str) {
+                    Some(uid) => by_uid.get(uid).copied(),
+                    None => original.get(i),
+                };
+                if let Some(original_item) = original_item {
+                    restore_key_order(item, original_item);
+                }
+            }
+        },
+        _ => {}
+    }
+}
+
+// ===< Migrations >=== //
+
+/// Version 1 had no version field and older editors didn't always write empty collections,
+/// version 2 always has them
+fn migrate_v1_to_v2(project: &mut Value) -> Result<(), String> {
+    let commands = project.pointer_mut("/content/commands")
+        .and_then(Value::as_array_mut)
+        .ok_or("Project has no commands")?;
+
+    for command in commands.iter_mut() {
+        let command = command.as_object_mut().ok_or("Command is not an object")?;
+        let options = command.entry("options").or_insert_with(|| Value::Array(vec![]));
+        for option in options.as_array_mut().ok_or("Command options are not an array")? {
+            if let Some(option) = option.as_object_mut() {
+                option.entry("choices").or_insert_with(|| Value::Array(vec![]));
+            }
+        }
+
+        let nodes = command.get_mut("flow")
+            .and_then(|flow| flow.get_mut("nodes"))
+            .and_then(Value::as_array_mut)
+            .ok_or("Command has no flow")?;
+        for node in nodes.iter_mut().filter_map(Value::as_object_mut) {
+            node.entry("inputHardcoded").or_insert_with(|| Value::Object(Default::default()));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn v1_project() -> Value {
+        json!({
+            "metadata": { "name": "test" },
+            "content": {
+                "commands": [{
+                    "uid": "c1",
+                    "name": "ping",
+                    "description": "",
+                    "flow": {
+                        "nodes": [{ "uid": "n1", "type": "reply", "x": 10, "y": 20 }],
+                        "connections": []
+                    }
+                }]
+            }
+        })
+    }
+
+    #[test]
+    fn migrates_v1_to_current() {
+        let mut project = v1_project();
+        assert_eq!(migrate(&mut project).unwrap(), 1);
+        assert_eq!(format_version(&project), FORMAT_VERSION);
+
+        let command = &project["content"]["commands"][0];
+        assert_eq!(command["options"], json!([]));
+        assert_eq!(command["flow"]["nodes"][0]["inputHardcoded"], json!({}));
+        // Editor data is untouched
+        assert_eq!(command["flow"]["nodes"][0]["x"], json!(10));
+
+        let data = from_migrated(project).unwrap();
+        assert_eq!(data.metadata.format_version, FORMAT_VERSION);
+        assert_eq!(data.content.commands[0].flow.nodes[0].node_type, "reply");
+    }
+
+    #[test]
+    fn fills_missing_choices_of_options() {
+        let mut project = v1_project();
+        project["content"]["commands"][0]["options"] = json!([{ "name": "user", "description": "", "type": 6, "required": true }]);
+        migrate(&mut project).unwrap();
+        assert_eq!(project["content"]["commands"][0]["options"][0]["choices"], json!([]));
+    }
+
+    #[test]
+    fn current_version_is_not_changed() {
+        let mut project = v1_project();
+        migrate(&mut project).unwrap();
+        let migrated = project.clone();
+
+        assert_eq!(migrate(&mut project).unwrap(), FORMAT_VERSION);
+        assert_eq!(project, migrated);
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let mut project = v1_project();
+        project["metadata"]["formatVersion"] = json!(FORMAT_VERSION + 1);
+        let original = project.clone();
+
+        assert!(matches!(migrate(&mut project), Err(ProjectLoadError::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1));
+        assert_eq!(project, original);
+    }
+
+    #[test]
+    fn rejects_version_zero() {
+        let mut project = v1_project();
+        project["metadata"]["formatVersion"] = json!(0);
+        assert!(matches!(migrate(&mut project), Err(ProjectLoadError::InvalidVersion(0))));
+        assert!(matches!(parse_project(project), Err(ProjectLoadError::InvalidVersion(0))));
+    }
+
+    #[test]
+    fn reports_version_of_failed_migration() {
+        let mut project = json!({ "metadata": { "name": "test" }, "content": {} });
+        assert!(matches!(migrate(&mut project), Err(ProjectLoadError::Migration { from: 1, .. })));
+
+        let mut project = json!({ "content": { "commands": [] } });
+        assert!(matches!(migrate(&mut project), Err(ProjectLoadError::Migration { from: 1, .. })));
+    }
+
+    #[test]
+    fn upgrades_file_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("project.dbp");
+        std::fs::write(&path, v1_project().to_string()).unwrap();
+
+        assert_eq!(upgrade_project_file(&path).unwrap(), 1);
+        let (upgraded, _) = read_project(&path).unwrap();
+        assert_eq!(format_version(&upgraded), FORMAT_VERSION);
+
+        // Second upgrade has nothing to do and leaves the file alone
+        let written = std::fs::read(&path).unwrap();
+        assert_eq!(upgrade_project_file(&path).unwrap(), FORMAT_VERSION);
+        assert_eq!(std::fs::read(&path).unwrap(), written);
+    }
+
+    /// Current format project with editor fields, unknown fields and keys out of the order of the structures
+    fn current_project() -> Value {
+        json!({
+            "content": {
+                "commands": [{
+                    "name": "ping",
+                    "uid": "c1",
+                    "options": [],
+                    "description": "",
+                    "flow": {
+                        "connections": [],
+                        "nodes": [{ "uid": "n1", "type": "reply", "inputHardcoded": { "text": "pong" }, "x": 10.5, "y": -3, "selected": true }],
+                        "zoom": 1.25
+                    },
+                    "color": "#ff0000"
+                }],
+                "settings": { "prefix": "!" }
+            },
+            "metadata": { "formatVersion": FORMAT_VERSION, "name": "test", "author": "someone" },
+            "plugins": ["a", "b"]
+        })
+    }
+
+    fn load_text(text: &str) -> DisbotterProjectData {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("project.dbp");
+        std::fs::write(&path, text).unwrap();
+        load_project(&path).unwrap()
+    }
+
+    #[test]
+    fn compact_project_round_trips() {
+        let text = current_project().to_string();
+        assert_eq!(load_text(&text).to_json_string(), text);
+
+        // Projects from the editor have no file and are compact as well
+        assert_eq!(parse_project(current_project()).unwrap().to_json_string(), text);
+    }
+
+    #[test]
+    fn formatted_project_round_trips() {
+        let text = serde_json::to_string_pretty(&current_project()).unwrap() + "\n";
+        assert_eq!(load_text(&text).to_json_string(), text);
+
+        let tabs = {
+            let mut out = vec![];
+            let mut serializer = serde_json::Serializer::with_formatter(&mut out, PrettyFormatter::with_indent(b"\t"));
+            current_project().serialize(&mut serializer).unwrap();
+            String::from_utf8(out).unwrap().replace('\n', "\r\n")
+        };
+        assert_eq!(load_text(&tabs).to_json_string(), tabs);
+    }
+
+    #[test]
+    fn modified_project_keeps_order_and_formatting() {
+        let text = serde_json::to_string_pretty(&current_project()).unwrap();
+        let mut project = load_text(&text);
+        project.metadata.name = "renamed".to_string();
+
+        let mut expected = current_project();
+        expected["metadata"]["name"] = json!("renamed");
+        assert_eq!(project.to_json_string(), serde_json::to_string_pretty(&expected).unwrap());
+    }
+
+    #[test]
+    fn input_counts_are_kept_as_they_were() {
+        let mut project = current_project();
+        let nodes = project.pointer_mut("/content/commands/0/flow/nodes").unwrap().as_array_mut().unwrap();
+        nodes[0]["inputCounts"] = json!({});
+        nodes.push(json!({ "uid": "n2", "inputCounts": { "values": 2, "keys": 1 }, "type": "join", "inputHardcoded": {} }));
+        let text = project.to_string();
+
+        let data = load_text(&text);
+        let nodes = &data.content.commands[0].flow.nodes;
+        assert_eq!(nodes[0].input_counts, Some(BTreeMap::new()));
+        assert_eq!(nodes[1].variadic_keys("values"), ["values[0]", "values[1]"]);
+        assert_eq!(data.to_json_string(), text);
+
+        // Nodes without the counts don't get them
+        assert_eq!(load_text(&current_project().to_string()).to_json_string(), current_project().to_string());
+    }
+
+    #[test]
+    fn upgrade_keeps_formatting() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("project.dbp");
+        std::fs::write(&path, serde_json::to_string_pretty(&v1_project()).unwrap() + "\n").unwrap();
+        upgrade_project_file(&path).unwrap();
+
+        let (upgraded, format) = read_project(&path).unwrap();
+        assert_eq!(format, JsonFormat::detect(&(serde_json::to_string_pretty(&upgraded).unwrap() + "\n")));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), serde_json::to_string_pretty(&upgraded).unwrap() + "\n");
+    }
+}
@@ -0,0 +1,362 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::Arc};
+
+use rhai::{Engine, OptimizationLevel, Scope, AST};
+
+use crate::{builder::CodeBuilder, cache::hash_bytes, compiler::CompilerError, loader::{node_from_ast, Node, NodeScriptLoadingError}, market::Lockfile, package::Package, project::DisbotterProjectData};
+
+/// Node script that was parsed once and holds everything needed to declare and compile the node
+#[derive(Clone)]
+pub struct RegisteredNode {
+    pub metadata: Arc<Node>,
+    ast: Arc<AST>,
+    /// Pure nodes have no flow ports, they are compiled when their outputs are used
+    pub is_pure: bool,
+    /// Node replaces node with the same id that was loaded before it
+    pub overrides: bool,
+    /// Hash of the script source, used in cache keys
+    pub source_hash: String,
+    /// Script the node was loaded from
+    pub path: PathBuf,
+}
+
+impl RegisteredNode {
+    /// Loads a single node script, nodes of a package get id prefixed with the package name
+    pub fn load(path: PathBuf, package: Option<Arc<Package>>, engine: &Engine) -> Result<Self, NodeScriptLoadingError> {
+        let source = std::fs::read_to_string(&path).map_err(|err| NodeScriptLoadingError::Io(path.clone(), err))?;
+        // The shared engine doesn't optimize, so the script is parsed only once and errors in the metadata
+        // can still point to their lines, the optimized copy is used for everything else
+        let unoptimized = engine.compile(&source).map_err(|err| NodeScriptLoadingError::InvalidScript(path.clone(), err.into()))?;
+        let ast = engine.optimize_ast(&Scope::new(), unoptimized.clone(), OptimizationLevel::Simple);
+        let is_pure = ast.iter_literal_variables(true, false).any(|(name, _, _)| name == "pure");
+        let overrides = ast.iter_literal_variables(true, false).any(|(name, _, _)| name == "overrides");
+        let mut metadata = node_from_ast(&ast, &unoptimized, &path)?;
+        if let Some(package) = package {
+            metadata.id = package.node_id(&metadata.id);
+            metadata.package = Some(package.as_ref().clone());
+        }
+
+        Ok(Self {
+            metadata: Arc::new(metadata),
+            ast: Arc::new(ast),
+            is_pure,
+            overrides,
+            source_hash: hash_bytes(source.as_bytes()),
+            path,
+        })
+    }
+
+    /// Loads script from the node directory `root` with the package it belongs to, the way the registry does.
+    /// Installed packages are checked against the lockfile of the directory by the caller
+    pub fn load_in(root: &Path, path: PathBuf, engine: &Engine) -> Result<Self, NodeScriptLoadingError> {
+        Self::load(path.clone(), Package::find(&path, root)?, engine)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.metadata.id
+    }
+
+    /// Runs `action` function of the script, which generates code of the node
+    pub fn call_action(&self, engine: &Engine, builder: CodeBuilder) -> Result<(), CompilerError> {
+        let mut scope = Scope::new();
+        engine.call_fn::<()>(&mut scope, &self.ast, "action", (builder,)).map_err(CompilerError::RhaiError)?;
+        Ok(())
+    }
+}
+
+/// All nodes that can be used in projects, by their id.
+/// Nodes keep the order they were loaded in, scripts of a directory are loaded in the order of their paths
+/// (see `script_files`), so exported declarations and the winners of overrides don't depend on the file system.
+/// Every id can be declared only once, unless the later script is marked with `overrides`,
+/// which is used by node libraries that replace builtin nodes
+#[derive(Clone, Default)]
+pub struct NodeRegistry {
+    nodes: Vec<RegisteredNode>,
+    index: HashMap<String, usize>,
+}
+
+impl NodeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates registry from nodes in the order they were loaded
+    pub fn from_nodes(nodes: impl IntoIterator<Item = RegisteredNode>) -> Result<Self, NodeScriptLoadingError> {
+        let mut registry = Self::new();
+        for node in nodes {
+            registry.insert(node)?;
+        }
+        registry.check_replacements()?;
+        Ok(registry)
+    }
+
+    /// Loads nodes from all given directories, later directories can override nodes of the earlier ones.
+    /// Directory can be a package, a directory of scripts or a root containing many packages
+    pub fn load(paths: &[PathBuf], engine: &Engine) -> Result<Self, NodeScriptLoadingError> {
+        let mut registry = Self::new();
+        for path in paths {
+            registry.load_dir(path, engine)?;
+        }
+        registry.check_replacements()?;
+        Ok(registry)
+    }
+
+    /// Loads all node scripts from the directory and its subdirectories, scripts belong to the closest package above them.
+    /// If the directory has a lockfile, installed packages have to match it
+    pub fn load_dir(&mut self, path: &Path, engine: &Engine) -> Result<(), NodeScriptLoadingError> {
+        if let Some(lockfile) = Lockfile::read(path)? {
+            lockfile.verify()?;
+        }
+        for script in script_files(path)? {
+            let node = RegisteredNode::load_in(path, script, engine)?;
+            self.insert(node)?;
+        }
+        Ok(())
+    }
+
+    /// Adds node to the registry.
+    /// If there already is a node with the same id, it is replaced only if the new node overrides it
+    pub fn insert(&mut self, node: RegisteredNode) -> Result<(), NodeScriptLoadingError> {
+        match self.index.get(node.id()) {
+            Some(&i) if node.overrides => {
+                log::info!("Node {} from {} is overridden by {}", node.id(), self.nodes[i].path.display(), node.path.display());
+                // Overriding node takes the place of the original one, so the declarations keep their order
+                self.nodes[i] = node;
+            },
+            Some(&i) => {
+                return Err(NodeScriptLoadingError::DuplicateId {
+                    id: node.id().to_string(),
+                    first: self.nodes[i].path.clone(),
+                    second: node.path,
+                });
+            },
+            None => {
+                self.index.insert(node.id().to_string(), self.nodes.len());
+                self.nodes.push(node);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that replacements of all deprecated nodes exist.
+    /// Replacement can come from any directory, so it is checked only after all nodes are loaded
+    fn check_replacements(&self) -> Result<(), NodeScriptLoadingError> {
+        for node in self.nodes.iter() {
+            let replacement = node.metadata.deprecated.as_ref().and_then(|deprecation| deprecation.replacement.as_ref());
+            if let Some(replacement) = replacement.filter(|replacement| self.get(replacement).is_none()) {
+                return Err(NodeScriptLoadingError::InvalidMetadata {
+                    script: node.path.clone(),
+                    line: None,
+                    message: format!("replacement \"{}\" of the deprecated node is not a loaded node (nodes of packages have ids like \"package/node\")", replacement),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&RegisteredNode> {
+        self.index.get(id).map(|&i| &self.nodes[i])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RegisteredNode> {
+        self.nodes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Packages of the nodes used in the project, every package is listed once
+    pub fn packages_used_by(&self, project: &DisbotterProjectData) -> Vec<&Package> {
+        let mut packages: Vec<&Package> = vec![];
+        let used = project.content.commands.iter()
+            .flat_map(|command| command.flow.nodes.iter())
+            .filter_map(|node| self.get(&node.node_type)?.metadata.package.as_ref());
+        for package in used {
+            if !packages.iter().any(|p| p.name == package.name) {
+                packages.push(package);
+            }
+        }
+        packages
+    }
+
+    /// Metadata of all nodes, this is what the web editor gets
+    pub fn declarations(&self) -> Vec<&Node> {
+        self.nodes.iter().map(|node| node.metadata.as_ref()).collect()
+    }
+}
+
+/// Checks if the file is a node script
+pub fn is_node_script(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "rhai")
+}
+
+/// Finds all node scripts in the directory and its subdirectories, sorted by their path.
+/// Order of `read_dir` is unspecified, so this is the only order scripts are registered in
+pub fn script_files(dir: &Path) -> Result<Vec<PathBuf>, NodeScriptLoadingError> {
+    fn collect(dir: &Path, scripts: &mut Vec<PathBuf>) -> Result<(), NodeScriptLoadingError> {
+        let entries = std::fs::read_dir(dir).map_err(|err| NodeScriptLoadingError::Io(dir.to_path_buf(), err))?;
+        for entry in entries {
+            let path = entry.map_err(|err| NodeScriptLoadingError::Io(dir.to_path_buf(), err))?.path();
+            if path.is_dir() {
+                collect(&path, scripts)?;
+            } else if is_node_script(&path) {
+                scripts.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut scripts = vec![];
+    collect(dir, &mut scripts)?;
+    scripts.sort();
+    Ok(scripts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::shared_engine;
+
+    fn write_script(path: &Path, id: &str, constants: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, format!(
+            "const id = \"{}\";\nconst title = \"{}\";\nconst description = \"\";\nconst category = \"Test\";\n{}\n\
+            const inputs = #{{}};\nconst outputs = #{{}};\nfn action(builder) {{}}\n",
+            id, id, constants
+        )).unwrap();
+    }
+
+    fn load(dirs: &[&Path]) -> Result<NodeRegistry, NodeScriptLoadingError> {
+        NodeRegistry::load(&dirs.iter().map(|dir| dir.to_path_buf()).collect::<Vec<PathBuf>>(), &shared_engine())
+    }
+
+    #[test]
+    fn scripts_are_loaded_in_path_order() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["c.rhai", "a/z.rhai", "a.rhai", "b/y.rhai"] {
+            write_script(&dir.path().join(name), &name.replace(['/', '.'], "_"), "");
+        }
+
+        let registry = load(&[dir.path()]).unwrap();
+        let ids = registry.iter().map(|node| node.id()).collect::<Vec<&str>>();
+        assert_eq!(ids, ["a_z_rhai", "a_rhai", "b_y_rhai", "c_rhai"]);
+        assert_eq!(script_files(dir.path()).unwrap(), registry.iter().map(|node| node.path.clone()).collect::<Vec<PathBuf>>());
+    }
+
+    #[test]
+    fn later_script_overrides_in_the_same_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        // Written in reverse, so the order of the directory entries is likely not the order of the paths
+        write_script(&dir.path().join("b.rhai"), "greet", "const overrides;");
+        write_script(&dir.path().join("a.rhai"), "greet", "");
+
+        let registry = load(&[dir.path()]).unwrap();
+        assert_eq!(registry.len(), 1);
+        assert_eq!(registry.get("greet").unwrap().path, dir.path().join("b.rhai"));
+    }
+
+    #[test]
+    fn later_directory_overrides() {
+        let builtin = tempfile::tempdir().unwrap();
+        let library = tempfile::tempdir().unwrap();
+        write_script(&builtin.path().join("greet.rhai"), "greet", "");
+        write_script(&builtin.path().join("other.rhai"), "other", "");
+        write_script(&library.path().join("greet.rhai"), "greet", "const overrides;");
+
+        let registry = load(&[builtin.path(), library.path()]).unwrap();
+        assert_eq!(registry.get("greet").unwrap().path, library.path().join("greet.rhai"));
+        // Overriding node keeps the place of the original one
+        assert_eq!(registry.iter().map(|node| node.id()).collect::<Vec<&str>>(), ["greet", "other"]);
+
+        // Without overrides the library can't replace the node
+        write_script(&library.path().join("greet.rhai"), "greet", "");
+        assert!(matches!(load(&[builtin.path(), library.path()]), Err(NodeScriptLoadingError::DuplicateId { .. })));
+    }
+
+    #[test]
+    fn replacements_of_deprecated_nodes_have_to_exist() {
+        let builtin = tempfile::tempdir().unwrap();
+        let library = tempfile::tempdir().unwrap();
+        write_script(&builtin.path().join("old.rhai"), "old", "const deprecated = \"new\";");
+        write_script(&builtin.path().join("gone.rhai"), "gone", "const deprecated;");
+        write_script(&library.path().join("new.rhai"), "new", "");
+
+        // Replacement from a later directory
+        load(&[builtin.path(), library.path()]).unwrap();
+
+        match load(&[builtin.path()]) {
+            Err(NodeScriptLoadingError::InvalidMetadata { script, message, .. }) => {
+                assert_eq!(script, builtin.path().join("old.rhai"));
+                assert!(message.contains("\"new\""), "{}", message);
+            },
+            other => panic!("expected invalid metadata, got {:?}", other.map(|registry| registry.len())),
+        }
+    }
+
+    fn write_package(dir: &Path, name: &str, npm_dependencies: &str) {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join(crate::package::PACKAGE_MANIFEST), format!(
+            "name = \"{}\"\nversion = \"1.0.0\"\nauthor = \"me\"\n\n[npm-dependencies]\n{}\n",
+            name, npm_dependencies
+        )).unwrap();
+    }
+
+    #[test]
+    fn package_nodes_are_prefixed_and_used_packages_listed_once() {
+        let dir = tempfile::tempdir().unwrap();
+        write_package(&dir.path().join("greet"), "greet", "\"greet-lib\" = \"^1.0\"");
+        write_script(&dir.path().join("greet/hello.rhai"), "hello", "");
+        write_script(&dir.path().join("greet/nested/bye.rhai"), "bye", "");
+        write_package(&dir.path().join("unused"), "unused", "");
+        write_script(&dir.path().join("unused/node.rhai"), "node", "");
+        write_script(&dir.path().join("plain.rhai"), "plain", "");
+
+        let registry = load(&[dir.path()]).unwrap();
+        assert!(registry.get("hello").is_none());
+        let hello = registry.get("greet/hello").unwrap();
+        assert_eq!(hello.metadata.package.as_ref().unwrap().name, "greet");
+        // Scripts in subdirectories belong to the package above them
+        assert_eq!(registry.get("greet/bye").unwrap().metadata.package.as_ref().unwrap().name, "greet");
+        assert!(registry.get("plain").unwrap().metadata.package.is_none());
+
+        let project: DisbotterProjectData = serde_json::from_value(serde_json::json!({
+            "metadata": { "name": "test", "formatVersion": 2 },
+            "content": { "commands": [{
+                "uid": "c1", "name": "test", "description": "", "options": [],
+                "flow": {
+                    "nodes": [
+                        { "uid": "a", "type": "greet/hello", "inputHardcoded": {} },
+                        { "uid": "b", "type": "greet/bye", "inputHardcoded": {} },
+                        { "uid": "c", "type": "greet/hello", "inputHardcoded": {} },
+                        { "uid": "d", "type": "plain", "inputHardcoded": {} },
+                    ],
+                    "connections": [],
+                },
+            }] },
+        })).unwrap();
+        let packages = registry.packages_used_by(&project);
+        assert_eq!(packages.iter().map(|package| package.name.as_str()).collect::<Vec<&str>>(), ["greet"]);
+        assert_eq!(packages[0].npm_dependencies.get("greet-lib").map(String::as_str), Some("^1.0"));
+    }
+
+    #[test]
+    fn duplicate_ids_in_the_same_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        write_script(&dir.path().join("b.rhai"), "greet", "");
+        // Override of a node that doesn't exist yet is just a node
+        write_script(&dir.path().join("a.rhai"), "greet", "const overrides;");
+
+        match load(&[dir.path()]) {
+            Err(NodeScriptLoadingError::DuplicateId { id, first, second }) => {
+                assert_eq!(id, "greet");
+                assert_eq!(first, dir.path().join("a.rhai"));
+                assert_eq!(second, dir.path().join("b.rhai"));
+            },
+            other => panic!("expected duplicate id, got {:?}", other.map(|registry| registry.len())),
+        }
+    }
+}
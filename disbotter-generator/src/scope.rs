@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use crate::compiler::PortIdentifier;
+
+/// Interned port identifier, cheap to copy and hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PortId(u32);
+
+/// Variables of the ports visible at the current place in the generated code.
+/// Scope is a chain of frames, every block pushes a new frame that shadows the outer ones
+/// and is dropped with everything set inside it when the block ends.
+/// Entering a block doesn't copy anything, lookups walk the chain from the innermost frame.
+/// Ports are interned, so a lookup hashes the full identifier once and only the id in every frame
+#[derive(Debug, Clone)]
+pub struct VarScope {
+    ids: HashMap<PortIdentifier, PortId>,
+    frames: Vec<HashMap<PortId, String>>,
+}
+
+impl Default for VarScope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VarScope {
+    pub fn new() -> Self {
+        Self {
+            ids: HashMap::new(),
+            frames: vec![HashMap::new()],
+        }
+    }
+
+    /// Returns id of the port, ports are interned the first time they are seen
+    pub fn intern(&mut self, port: PortIdentifier) -> PortId {
+        let next = PortId(self.ids.len() as u32);
+        *self.ids.entry(port).or_insert(next)
+    }
+
+    /// Gets variable of the port from the innermost frame that has it
+    pub fn get(&self, port: &PortIdentifier) -> Option<&String> {
+        let id = self.ids.get(port)?;
+        self.frames.iter().rev().find_map(|frame| frame.get(id))
+    }
+
+    pub fn contains(&self, port: &PortIdentifier) -> bool {
+        self.get(port).is_some()
+    }
+
+    /// Sets variable of the port in the innermost frame, shadowing outer frames
+    pub fn insert(&mut self, port: PortIdentifier, var: String) {
+        let id = self.intern(port);
+        self.frames.last_mut().unwrap().insert(id, var);
+    }
+
+    /// Sets variable of the port only if it isn't visible already
+    pub fn insert_if_missing(&mut self, port: PortIdentifier, var: impl FnOnce() -> String) {
+        if !self.contains(&port) {
+            self.insert(port, var());
+        }
+    }
+
+    /// Enters a new block
+    pub fn push(&mut self) {
+        self.frames.push(HashMap::new());
+    }
+
+    /// Leaves the current block, dropping everything set inside it.
+    /// The outermost frame is never dropped
+    pub fn pop(&mut self) {
+        if self.frames.len() > 1 {
+            self.frames.pop();
+        }
+    }
+
+    /// Removes all variables and blocks, ids of the ports are forgotten too, so the scope doesn't grow between flows
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.frames.clear();
+        self.frames.push(HashMap::new());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(key: &str) -> PortIdentifier {
+        PortIdentifier::Output { node_uid: "n1".to_string(), port_key: key.to_string() }
+    }
+
+    #[test]
+    fn inner_frame_shadows_outer() {
+        let mut scope = VarScope::new();
+        scope.insert(port("a"), "outer".to_string());
+        scope.push();
+        assert_eq!(scope.get(&port("a")).unwrap(), "outer");
+        scope.insert(port("a"), "inner".to_string());
+        assert_eq!(scope.get(&port("a")).unwrap(), "inner");
+        scope.pop();
+        assert_eq!(scope.get(&port("a")).unwrap(), "outer");
+    }
+
+    #[test]
+    fn pop_drops_variables_set_in_block() {
+        let mut scope = VarScope::new();
+        scope.push();
+        scope.insert(port("a"), "inner".to_string());
+        scope.pop();
+        assert!(!scope.contains(&port("a")));
+    }
+
+    #[test]
+    fn outermost_frame_is_never_popped() {
+        let mut scope = VarScope::new();
+        scope.insert(port("a"), "outer".to_string());
+        scope.pop();
+        scope.pop();
+        assert_eq!(scope.get(&port("a")).unwrap(), "outer");
+    }
+
+    #[test]
+    fn insert_if_missing_sees_outer_frames() {
+        let mut scope = VarScope::new();
+        scope.insert(port("a"), "outer".to_string());
+        scope.push();
+        scope.insert_if_missing(port("a"), || "inner".to_string());
+        scope.insert_if_missing(port("b"), || "inner".to_string());
+        scope.pop();
+        assert_eq!(scope.get(&port("a")).unwrap(), "outer");
+        assert!(!scope.contains(&port("b")));
+    }
+
+    #[test]
+    fn clear_forgets_ports() {
+        let mut scope = VarScope::new();
+        scope.push();
+        scope.insert(port("a"), "a".to_string());
+        scope.clear();
+        assert!(scope.ids.is_empty());
+        assert_eq!(scope.frames.len(), 1);
+        assert_eq!(scope.intern(port("b")), PortId(0));
+    }
+}
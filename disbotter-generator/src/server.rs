@@ -3,7 +3,10 @@ use std::{sync::{Arc, Mutex}, path::PathBuf, io::Write, process::{Stdio, Child}}
 use tokio::runtime::Runtime;
 use actix_web::{web, App, HttpResponse, HttpServer, middleware::Logger};
 
-use crate::compiler::{DisbotterProjectData, NodesJSCompiler, AvailableNode};
+use crate::{compiler::NodesJSCompiler, registry::NodeRegistry, cache::MemoryCache, project::parse_project, check::check_project_flows};
+
+/// Maximum number of compiled commands kept in memory
+const CACHE_CAPACITY: usize = 1024;
 
 pub struct DisbotterRESTApi {
     rt: Runtime,
@@ -12,9 +15,12 @@ pub struct DisbotterRESTApi {
 #[derive(serde::Serialize)]
 pub struct DisbotterRESTApiConfig {
     #[serde(skip)]
-    pub nodes: Arc<Vec<AvailableNode>>,
+    pub nodes: Arc<NodeRegistry>,
     #[serde(skip)]
     pub projects: Option<PathBuf>,
+    /// Cache of compiled commands, shared between all requests
+    #[serde(skip)]
+    pub cache: Arc<MemoryCache>,
     pub can_run: bool,
 
     pub def_token: String,
@@ -44,7 +50,7 @@ async fn config(config: web::Data<DisbotterRESTApiConfig>) -> HttpResponse {
 
 #[derive(serde::Deserialize)]
 struct CompileRequest {
-    project: Option<DisbotterProjectData>,
+    project: Option<serde_json::Value>,
 }
 
 async fn compile(mut req: web::Json<CompileRequest>, config: web::Data<DisbotterRESTApiConfig>) -> HttpResponse {
@@ -54,8 +60,19 @@ async fn compile(mut req: web::Json<CompileRequest>, config: web::Data<Disbotter
             return HttpResponse::BadRequest().body("Missing project data");
         }
     };
+    // Projects from older editors are upgraded before compiling
+    let project = match parse_project(project) {
+        Ok(project) => project,
+        Err(err) => {
+            return HttpResponse::BadRequest().body(format!("Invalid project data: {:?}", err));
+        }
+    };
     let mut compiler = NodesJSCompiler::new(project);
-    compiler.add_available_nodes_from_vec(&config.nodes);
+    compiler.set_registry(Arc::clone(&config.nodes));
+    compiler.set_cache(config.cache.clone());
+    if let Some(response) = flow_errors(&compiler) {
+        return response;
+    }
     let project = compiler.compile_project();
 
     match project {
@@ -68,9 +85,22 @@ async fn compile(mut req: web::Json<CompileRequest>, config: web::Data<Disbotter
     }
 }
 
+/// Checks flows of the project the same way the command line does before compiling,
+/// returns the response with all errors if there are any
+fn flow_errors(compiler: &NodesJSCompiler) -> Option<HttpResponse> {
+    let errors = check_project_flows(compiler).into_iter()
+        .filter(|diagnostic| diagnostic.is_error())
+        .map(|diagnostic| diagnostic.to_pretty())
+        .collect::<Vec<String>>();
+    match errors.is_empty() {
+        true => None,
+        false => Some(HttpResponse::BadRequest().body(format!("Project has errors:\n{}", errors.join("\n")))),
+    }
+}
+
 #[derive(serde::Deserialize)]
 struct RunRequest {
-    project: Option<DisbotterProjectData>,
+    project: Option<serde_json::Value>,
     token: Option<String>,
     client_id: Option<String>,
     guild_id: Option<String>,
@@ -88,6 +118,13 @@ async fn compile_and_run(mut req: web::Json<RunRequest>, config: web::Data<Disbo
             return HttpResponse::BadRequest().body("Missing project data");
         }
     };
+    // Projects from older editors are upgraded before compiling
+    let project = match parse_project(project) {
+        Ok(project) => project,
+        Err(err) => {
+            return HttpResponse::BadRequest().body(format!("Invalid project data: {:?}", err));
+        }
+    };
 
     // Then if there is no folder related to the project, create one
     let project_folder = config.projects.as_ref().unwrap().join(project.metadata.name.replace(" ", "_").clone());
@@ -110,7 +147,7 @@ async fn compile_and_run(mut req: web::Json<RunRequest>, config: web::Data<Disbo
         match git2::Repository::clone(url, &project_folder) {
             Ok(_) => {},
             Err(err) => {
-                println!("{:?}", err);
+                eprintln!("{:?}", err);
                 return HttpResponse::InternalServerError().body("Failed to clone example project");
             }
         };
@@ -149,7 +186,11 @@ async fn compile_and_run(mut req: web::Json<RunRequest>, config: web::Data<Disbo
 
     // Compile project
     let mut compiler = NodesJSCompiler::new(project);
-    compiler.add_available_nodes_from_vec(&config.nodes);
+    compiler.set_registry(Arc::clone(&config.nodes));
+    compiler.set_cache(config.cache.clone());
+    if let Some(response) = flow_errors(&compiler) {
+        return response;
+    }
     let project = compiler.compile_project();
 
     let project = match project {
@@ -159,8 +200,10 @@ async fn compile_and_run(mut req: web::Json<RunRequest>, config: web::Data<Disbo
         }
     };
 
-    // Write project to files
-    project.export_to_path(project_folder.join("src"));
+    // Write project to files, the project folder is managed by the server so modified files are just backed up
+    if let Err(err) = project.export_to_path(project_folder.join("src"), true) {
+        return HttpResponse::InternalServerError().body(format!("Failed to export project: {:?}", err));
+    }
 
     // Start the bot in the project folder
     let mut cmd = std::process::Command::new(cmds);
@@ -193,7 +236,7 @@ async fn compile_and_run(mut req: web::Json<RunRequest>, config: web::Data<Disbo
                 return HttpResponse::Ok().body("Code successfully compiled and bot started (old bot killed)");
             },
             Err(err) => {
-                println!("Failed to kill old bot: {:?}", err);
+                eprintln!("Failed to kill old bot: {:?}", err);
             }
         }
     }
@@ -240,18 +283,20 @@ impl DisbotterRESTApi {
         }
     }
 
-    pub fn start(&self, nodes: Arc<Vec<AvailableNode>>, projects: Option<PathBuf>) -> std::io::Result<()> {
+    pub fn start(&self, nodes: Arc<NodeRegistry>, projects: Option<PathBuf>, address: (&str, u16)) -> std::io::Result<()> {
         #[cfg(debug_assertions)]
         {
-            println!("Starting server in debug mode...");
+            eprintln!("Starting server in debug mode...");
             std::env::set_var("RUST_LOG", "actix_web=debug");
             env_logger::try_init().ok();
         }
 
         self.rt.block_on(async {
-            println!("Starting server...");
+            // Stdout is kept for the JSON result of the command
+            eprintln!("Starting server...");
 
             let state = web::Data::new(DisbotterRESTApiState::default());
+            let cache = Arc::new(MemoryCache::new(CACHE_CAPACITY));
             
             HttpServer::new(move || {
                 let cors = actix_cors::Cors::permissive()
@@ -260,12 +305,14 @@ impl DisbotterRESTApi {
                     })
                     .allowed_origin("https://disbotter.olix3001.xyz");
                 let nodes = nodes.clone();
+                let cache = cache.clone();
                 App::new()
                     .wrap(cors)
                     .wrap(Logger::default())
                     .app_data(web::Data::new(DisbotterRESTApiConfig {
                         nodes,
                         projects: projects.clone(),
+                        cache,
                         can_run: projects.is_some(),
                         def_token: std::env::var("DISCORD_TOKEN").unwrap_or("".to_string()),
                         def_client_id: std::env::var("DISCORD_CLIENT_ID").unwrap_or("".to_string()),
@@ -278,7 +325,7 @@ impl DisbotterRESTApi {
                     .route("/cnr", web::post().to(compile_and_run))
                     .route("/kill", web::post().to(kill_current))
             })
-            .bind(("127.0.0.1", 3000))?
+            .bind(address)?
             .run()
             .await
         })
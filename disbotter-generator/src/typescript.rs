@@ -0,0 +1,132 @@
+use crate::loader::{DataType, NodeIOTy};
+
+/// Struct tags that have a known TypeScript representation.
+/// Tags are checked in order, so more specific tags have to come first
+/// (for example `text_channel` before `channel`)
+const STRUCT_TAG_TYPES: &[(&str, &str)] = &[
+    ("interaction", "ChatInputCommandInteraction"),
+    ("member", "GuildMember"),
+    ("text_channel", "TextChannel"),
+    ("dm_channel", "DMChannel"),
+    ("guild_channel", "GuildChannel"),
+    ("channel", "Channel"),
+    ("guild", "Guild"),
+    ("user", "User"),
+    ("user_resolvable", "UserResolvable"),
+];
+
+/// Module that all struct tag types are imported from
+const STRUCT_TAG_MODULE: &str = "discord.js";
+
+/// TypeScript type used for a port in the generated code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TsType {
+    /// Type as it is written in the code, for example `GuildMember`
+    pub name: String,
+    /// Module the type has to be imported from, if any
+    pub import: Option<String>,
+}
+
+impl TsType {
+    /// Creates a builtin type that does not need to be imported
+    pub fn builtin(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            import: None,
+        }
+    }
+
+    /// Creates a type that has to be imported from the given module
+    pub fn imported(name: &str, module: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            import: Some(module.to_string()),
+        }
+    }
+
+    /// Returns the TypeScript type of a port declaration.
+    /// Explicit `ts_type` from the node script takes precedence over the type derived from
+    /// the data type and struct tags. Returns `None` if the type can't be determined,
+    /// in that case the type should be inferred by TypeScript
+    pub fn from_port(ty: &NodeIOTy) -> Option<Self> {
+        if let Some(name) = &ty.ts_type {
+            return Some(Self {
+                name: name.clone(),
+                import: ty.ts_import.clone(),
+            });
+        }
+
+        match ty.ty {
+            DataType::Number => Some(Self::builtin("number")),
+            DataType::Text => Some(Self::builtin("string")),
+            DataType::Boolean => Some(Self::builtin("boolean")),
+            DataType::Structure => Self::from_struct_tags(&ty.struct_tags),
+            DataType::Flow | DataType::Any => None,
+        }
+    }
+
+    /// Returns the TypeScript type of the most specific known struct tag
+    pub fn from_struct_tags(tags: &[String]) -> Option<Self> {
+        STRUCT_TAG_TYPES.iter()
+            .find(|(tag, _)| tags.iter().any(|t| t == tag))
+            .map(|(_, name)| Self::imported(name, STRUCT_TAG_MODULE))
+    }
+
+    /// Returns the TypeScript type of a hardcoded value from the editor
+    pub fn from_value(value: &serde_json::Value) -> Option<Self> {
+        match value {
+            serde_json::Value::String(_) => Some(Self::builtin("string")),
+            serde_json::Value::Number(_) => Some(Self::builtin("number")),
+            serde_json::Value::Bool(_) => Some(Self::builtin("boolean")),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(ty: DataType, tags: &[&str]) -> NodeIOTy {
+        NodeIOTy {
+            ty,
+            struct_tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn primitive_ports() {
+        assert_eq!(TsType::from_port(&port(DataType::Number, &[])), Some(TsType::builtin("number")));
+        assert_eq!(TsType::from_port(&port(DataType::Text, &[])), Some(TsType::builtin("string")));
+        assert_eq!(TsType::from_port(&port(DataType::Boolean, &[])), Some(TsType::builtin("boolean")));
+        assert_eq!(TsType::from_port(&port(DataType::Any, &[])), None);
+        assert_eq!(TsType::from_port(&port(DataType::Flow, &[])), None);
+    }
+
+    #[test]
+    fn explicit_ts_type_wins() {
+        let ty = NodeIOTy {
+            ts_type: Some("Role".to_string()),
+            ts_import: Some("discord.js".to_string()),
+            ..port(DataType::Structure, &["member"])
+        };
+        assert_eq!(TsType::from_port(&ty), Some(TsType::imported("Role", "discord.js")));
+    }
+
+    #[test]
+    fn most_specific_struct_tag() {
+        let tags = ["channel", "guild_channel", "text_channel"].map(str::to_string);
+        assert_eq!(TsType::from_struct_tags(&tags), Some(TsType::imported("TextChannel", "discord.js")));
+        assert_eq!(TsType::from_port(&port(DataType::Structure, &["user", "user_resolvable"])), Some(TsType::imported("User", "discord.js")));
+        assert_eq!(TsType::from_port(&port(DataType::Structure, &["unknown"])), None);
+    }
+
+    #[test]
+    fn hardcoded_values() {
+        assert_eq!(TsType::from_value(&serde_json::json!("text")), Some(TsType::builtin("string")));
+        assert_eq!(TsType::from_value(&serde_json::json!(1.5)), Some(TsType::builtin("number")));
+        assert_eq!(TsType::from_value(&serde_json::json!(false)), Some(TsType::builtin("boolean")));
+        assert_eq!(TsType::from_value(&serde_json::json!(null)), None);
+    }
+}
@@ -0,0 +1,389 @@
+use std::{collections::{BTreeMap, BTreeSet}, path::{Path, PathBuf}, sync::{mpsc, Arc}, time::Duration};
+
+use colored::*;
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde_json::json;
+
+use crate::{cache::CompileCache, check::check_project_flows, compiler::{shared_engine, NodesJSCompiler}, registry::{is_node_script, script_files, NodeRegistry, RegisteredNode}, loader::NodeScriptLoadingError, market::{Lockfile, LOCK_FILE}, package::manifest_dir, output::{Output, EXIT_COMPILE, EXIT_EXPORT, EXIT_LOAD}, project::{load_project, DisbotterProjectData}};
+
+/// Changes that come shortly after each other are handled together, so saving many files at once
+/// (or an editor writing one file in several steps) triggers only one build
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+const WAITING: &str = "Watching for changes...";
+
+/// Maximum number of compiled commands kept in memory when the disk cache is disabled
+pub const CACHE_CAPACITY: usize = 1024;
+
+pub struct WatchOptions {
+    pub project: PathBuf,
+    pub output: PathBuf,
+    /// Directories containing the nodes
+    pub nodes: Vec<PathBuf>,
+    pub force: bool,
+    /// Cache shared by all builds, commands are compiled again only if they or their nodes changed
+    pub cache: Arc<dyn CompileCache>,
+}
+
+/// Project and node scripts kept loaded between builds, so only files that changed are loaded again
+struct WatchState {
+    options: WatchOptions,
+    project: Result<DisbotterProjectData, String>,
+    /// Node scripts by their path
+    scripts: BTreeMap<PathBuf, RegisteredNode>,
+    /// Errors of node scripts that couldn't be loaded by their path
+    broken_scripts: BTreeMap<PathBuf, String>,
+    /// Errors of lockfiles that couldn't be read or don't match the installed packages, by their node directory
+    broken_lockfiles: BTreeMap<PathBuf, String>,
+}
+
+/// Compiles the project and compiles it again whenever the project file or any node script changes.
+/// Errors are reported and the watching continues, it returns only if the files can't be watched
+pub fn watch(mut options: WatchOptions, output: &Output) -> Result<(), notify::Error> {
+    // Events have absolute paths, so the watched paths have to be absolute too
+    options.project = canonicalize(&options.project)?;
+    options.nodes = options.nodes.iter().map(|dir| canonicalize(dir)).collect::<Result<_, _>>()?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    // Editors often save by replacing the file, which would end watching of the file itself
+    let project_dir = options.project.parent().unwrap_or(Path::new("/"));
+    watcher.watch(project_dir, RecursiveMode::NonRecursive)?;
+    for dir in options.nodes.iter() {
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+    }
+
+    let mut state = WatchState::load(options);
+    state.build(output);
+    output.info(WAITING.dimmed().to_string());
+
+    while let Ok(event) = receiver.recv() {
+        let mut changed = BTreeSet::new();
+        collect_paths(event, &mut changed);
+        while let Ok(event) = receiver.recv_timeout(DEBOUNCE) {
+            collect_paths(event, &mut changed);
+        }
+
+        if state.apply_changes(changed, output) {
+            state.build(output);
+            output.info(WAITING.dimmed().to_string());
+        }
+    }
+
+    Ok(())
+}
+
+impl WatchState {
+    fn load(options: WatchOptions) -> Self {
+        let mut state = Self {
+            project: Err(String::new()),
+            scripts: BTreeMap::new(),
+            broken_scripts: BTreeMap::new(),
+            broken_lockfiles: BTreeMap::new(),
+            options,
+        };
+        state.reload_project();
+        for dir in state.options.nodes.clone() {
+            state.verify_lockfile(&dir);
+            for script in scripts_in(&dir) {
+                state.reload_script(script);
+            }
+        }
+        state
+    }
+
+    /// Checks installed packages of the node directory against its lockfile again.
+    /// Returns true if the result is different than before
+    fn verify_lockfile(&mut self, dir: &Path) -> bool {
+        let result = Lockfile::read(dir).and_then(|lockfile| match lockfile {
+            Some(lockfile) => lockfile.verify(),
+            None => Ok(()),
+        });
+        let previous = match result {
+            Ok(()) => self.broken_lockfiles.remove(dir),
+            Err(err) => self.broken_lockfiles.insert(dir.to_path_buf(), err.to_pretty()),
+        };
+        previous != self.broken_lockfiles.get(dir).cloned()
+    }
+
+    fn reload_project(&mut self) {
+        self.project = load_project(&self.options.project).map_err(|err| err.to_pretty());
+    }
+
+    /// Loads script again, or forgets it if it was removed
+    fn reload_script(&mut self, path: PathBuf) {
+        self.scripts.remove(&path);
+        self.broken_scripts.remove(&path);
+        if !path.is_file() {
+            return;
+        }
+
+        let root = self.options.nodes.iter().find(|dir| path.starts_with(dir)).cloned().unwrap_or_default();
+        match RegisteredNode::load_in(&root, path.clone(), &shared_engine()) {
+            Ok(node) => { self.scripts.insert(path, node); },
+            Err(err) => { self.broken_scripts.insert(path, err.to_pretty()); },
+        }
+    }
+
+    /// Reloads changed files, returns true if anything the build depends on changed
+    fn apply_changes(&mut self, changed: BTreeSet<PathBuf>, output: &Output) -> bool {
+        // Any file of an installed package can be modified, added or removed, so lockfiles are checked after every change
+        let mut rebuild = false;
+        let changed_dirs = self.options.nodes.iter()
+            .filter(|dir| changed.iter().any(|path| path.starts_with(dir)))
+            .cloned()
+            .collect::<Vec<PathBuf>>();
+        for dir in changed_dirs.iter() {
+            rebuild |= self.verify_lockfile(dir);
+        }
+
+        for path in changed {
+            if path == self.options.project {
+                output.info(format!("{} {}", "Project changed:".cyan(), path.display()));
+                self.reload_project();
+                rebuild = true;
+            } else if self.options.nodes.iter().any(|dir| path.starts_with(dir)) {
+                if path.file_name().is_some_and(|name| name == LOCK_FILE) {
+                    // Packages were installed or updated, their scripts are loaded again
+                    output.info(format!("{} {}", "Lockfile changed:".cyan(), path.display()));
+                    for script in scripts_in(path.parent().unwrap_or(&path)) {
+                        self.reload_script(script);
+                    }
+                    rebuild = true;
+                } else if let Some(dir) = manifest_dir(&path) {
+                    // Package changed, so all its nodes get new ids
+                    output.info(format!("{} {}", "Package manifest changed:".cyan(), path.display()));
+                    for script in scripts_in(&dir) {
+                        self.reload_script(script);
+                    }
+                    rebuild = true;
+                } else if is_node_script(&path) {
+                    output.info(format!("{} {}", "Node script changed:".cyan(), path.display()));
+                    self.reload_script(path);
+                    rebuild = true;
+                } else if path.is_dir() {
+                    // Directory that was added or moved in
+                    for script in scripts_in(&path) {
+                        output.info(format!("{} {}", "Node script changed:".cyan(), script.display()));
+                        self.reload_script(script);
+                        rebuild = true;
+                    }
+                } else {
+                    // Directory that was removed or moved out
+                    let before = self.scripts.len() + self.broken_scripts.len();
+                    self.scripts.retain(|script, _| !script.starts_with(&path));
+                    self.broken_scripts.retain(|script, _| !script.starts_with(&path));
+                    rebuild |= self.scripts.len() + self.broken_scripts.len() != before;
+                }
+            }
+        }
+        rebuild
+    }
+
+    /// Builds registry from the loaded scripts.
+    /// Nodes are added in the order of the directories and scripts are kept sorted by their path,
+    /// which is the order of `script_files`, so the same nodes override each other as in a single compilation
+    fn registry(&self) -> Result<NodeRegistry, NodeScriptLoadingError> {
+        let nodes = self.options.nodes.iter()
+            .flat_map(|dir| self.scripts.iter().filter(move |(path, _)| path.starts_with(dir)))
+            .map(|(_, node)| node.clone());
+        NodeRegistry::from_nodes(nodes)
+    }
+
+    /// Compiles and exports the project, all problems are reported without exiting
+    fn build(&self, output: &Output) {
+        let path = self.options.project.display().to_string();
+
+        if !self.broken_scripts.is_empty() || !self.broken_lockfiles.is_empty() {
+            let details = self.broken_lockfiles.values().chain(self.broken_scripts.values()).cloned().collect::<Vec<String>>();
+            output.event(Some(EXIT_LOAD), "Failed to load node scripts".red().to_string(), &details, json!({}));
+            return;
+        }
+        let project = match &self.project {
+            Ok(project) => project.clone(),
+            Err(err) => {
+                output.event(Some(EXIT_LOAD), format!("{} {}", "Failed to load project:".red(), path.yellow()), std::slice::from_ref(err), json!({}));
+                return;
+            }
+        };
+
+        let nodes = match self.registry() {
+            Ok(nodes) => nodes,
+            Err(err) => {
+                output.event(Some(EXIT_LOAD), "Failed to load node scripts".red().to_string(), &[err.to_pretty()], json!({}));
+                return;
+            }
+        };
+
+        let mut compiler = NodesJSCompiler::new(project);
+        compiler.set_registry(Arc::new(nodes));
+        compiler.set_cache(Arc::clone(&self.options.cache));
+
+        // Errors in node actions are reported by the compilation itself
+        let diagnostics = check_project_flows(&compiler);
+        let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+        if errors > 0 {
+            let details = diagnostics.iter().map(|d| d.to_pretty()).collect::<Vec<String>>();
+            output.event(Some(EXIT_COMPILE), format!("{} {} ({} errors, {} warnings)", "Project has errors:".red(), path.yellow(), errors, diagnostics.len() - errors), &details, json!({
+                "diagnostics": diagnostics,
+            }));
+            return;
+        }
+        for diagnostic in diagnostics.iter() {
+            output.info(diagnostic.to_pretty());
+        }
+
+        let program = match compiler.compile_project() {
+            Ok(program) => program,
+            Err(err) => {
+                output.event(Some(EXIT_COMPILE), format!("{} {}", "Failed to compile project:".red(), path.yellow()), &[err.to_pretty()], json!({}));
+                return;
+            }
+        };
+
+        match program.export_to_path(self.options.output.clone(), self.options.force) {
+            Ok(report) => {
+                for backed_up in report.backed_up.iter() {
+                    output.info(format!("{} {} -> {}.bak", "Backed up modified file:".yellow(), backed_up, backed_up));
+                }
+                for removed in report.removed.iter() {
+                    output.info(format!("{} {}", "Removed stale file:".yellow(), removed));
+                }
+                output.event(None, format!("{} {}", "Successfully compiled project:".green(), path.yellow()), &[], json!({
+                    "written": report.written,
+                    "removed": report.removed,
+                    "backedUp": report.backed_up,
+                    "diagnostics": diagnostics,
+                }));
+            },
+            Err(err) => {
+                output.event(Some(EXIT_EXPORT), format!("{} {}", "Failed to export project:".red(), path.yellow()), &[err.to_pretty()], json!({}));
+            }
+        }
+    }
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf, notify::Error> {
+    path.canonicalize().map_err(|err| notify::Error::io(err).add_path(path.to_path_buf()))
+}
+
+/// Adds paths from the event to the changed paths, events that don't modify anything are ignored
+fn collect_paths(event: notify::Result<notify::Event>, changed: &mut BTreeSet<PathBuf>) {
+    match event {
+        // Loading the scripts opens them too, these events would trigger another build
+        Ok(event) if matches!(event.kind, EventKind::Access(_)) => {},
+        Ok(event) => changed.extend(event.paths),
+        Err(err) => log::warn!("Failed to watch files: {}", err),
+    }
+}
+
+/// Finds all node scripts in the directory, directories that can't be read have none
+fn scripts_in(dir: &Path) -> Vec<PathBuf> {
+    script_files(dir).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::MemoryCache;
+
+    fn write_script(path: &Path, id: &str, constants: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, format!(
+            "const id = \"{}\";\nconst title = \"{}\";\nconst description = \"\";\nconst category = \"Test\";\n{}\n\
+            const inputs = #{{}};\nconst outputs = #{{}};\nfn action(builder) {{}}\n",
+            id, id, constants
+        )).unwrap();
+    }
+
+    fn options(root: &Path, nodes: Vec<PathBuf>) -> WatchOptions {
+        WatchOptions {
+            project: root.join("project.dbp"),
+            output: root.join("out"),
+            nodes,
+            force: false,
+            cache: Arc::new(MemoryCache::new(CACHE_CAPACITY)),
+        }
+    }
+
+    fn changed(paths: &[PathBuf]) -> BTreeSet<PathBuf> {
+        paths.iter().cloned().collect()
+    }
+
+    #[test]
+    fn reloads_changed_files() {
+        let root = tempfile::tempdir().unwrap();
+        let nodes = root.path().join("nodes");
+        write_script(&nodes.join("a.rhai"), "a", "");
+        let mut state = WatchState::load(options(root.path(), vec![nodes.clone()]));
+        let output = Output::new(crate::output::OutputFormat::Json);
+        assert!(state.project.is_err());
+
+        // New script
+        write_script(&nodes.join("more/b.rhai"), "b", "");
+        assert!(state.apply_changes(changed(&[nodes.join("more/b.rhai")]), &output));
+        assert!(state.registry().unwrap().get("b").is_some());
+
+        // Broken script is reported until it is fixed
+        std::fs::write(nodes.join("a.rhai"), "const id = ").unwrap();
+        assert!(state.apply_changes(changed(&[nodes.join("a.rhai")]), &output));
+        assert!(state.broken_scripts.contains_key(&nodes.join("a.rhai")));
+        write_script(&nodes.join("a.rhai"), "a", "");
+        assert!(state.apply_changes(changed(&[nodes.join("a.rhai")]), &output));
+        assert!(state.broken_scripts.is_empty());
+
+        // Removed directory
+        std::fs::remove_dir_all(nodes.join("more")).unwrap();
+        assert!(state.apply_changes(changed(&[nodes.join("more")]), &output));
+        assert!(state.registry().unwrap().get("b").is_none());
+
+        // Files that aren't watched don't trigger a build
+        assert!(!state.apply_changes(changed(&[root.path().join("notes.txt")]), &output));
+        assert!(!state.apply_changes(changed(&[nodes.join("notes.txt")]), &output));
+
+        std::fs::write(root.path().join("project.dbp"), "{}").unwrap();
+        assert!(state.apply_changes(changed(&[root.path().join("project.dbp")]), &output));
+        assert!(state.project.is_err());
+    }
+
+    #[test]
+    fn build_writes_output() {
+        let root = tempfile::tempdir().unwrap();
+        let nodes = root.path().join("nodes");
+        write_script(&nodes.join("start.rhai"), crate::project::START_NODE, "const noFlowIn;");
+        std::fs::write(root.path().join("project.dbp"), json!({
+            "metadata": { "name": "test", "formatVersion": 2 },
+            "content": { "commands": [{
+                "uid": "c1", "name": "hello", "description": "", "options": [],
+                "flow": { "nodes": [{ "uid": "start", "type": crate::project::START_NODE, "inputHardcoded": {} }], "connections": [] },
+            }] },
+        }).to_string()).unwrap();
+
+        let state = WatchState::load(options(root.path(), vec![nodes]));
+        state.build(&Output::new(crate::output::OutputFormat::Json));
+        assert!(root.path().join("out/commands/hello.ts").is_file());
+        assert!(root.path().join("out").join(crate::builder::MANIFEST_FILE).is_file());
+    }
+
+    #[test]
+    fn registry_matches_single_compilation() {
+        let root = tempfile::tempdir().unwrap();
+        let builtin = root.path().join("builtin");
+        let library = root.path().join("library");
+        write_script(&builtin.join("b.rhai"), "greet", "const overrides;");
+        write_script(&builtin.join("a.rhai"), "greet", "");
+        write_script(&builtin.join("a/nested.rhai"), "nested", "");
+        write_script(&library.join("z.rhai"), "nested", "const overrides;");
+        write_script(&library.join("y.rhai"), "other", "");
+
+        let nodes = vec![builtin, library];
+        let state = WatchState::load(options(root.path(), nodes.clone()));
+        let watched = state.registry().unwrap();
+        let compiled = NodeRegistry::load(&nodes, &shared_engine()).unwrap();
+
+        let paths = |registry: &NodeRegistry| registry.iter().map(|node| (node.id().to_string(), node.path.clone())).collect::<Vec<(String, PathBuf)>>();
+        assert_eq!(paths(&watched), paths(&compiled));
+        assert_eq!(watched.get("greet").unwrap().path, nodes[0].join("b.rhai"));
+        assert_eq!(watched.get("nested").unwrap().path, nodes[1].join("z.rhai"));
+    }
+}
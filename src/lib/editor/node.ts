@@ -3,16 +3,80 @@ export interface NodeType {
 	title: string;
 	description: string;
 	category: string;
+	// Color of the header (hex)
+	color?: string;
+	// Image shown next to the title
+	icon?: string;
+	// Additional words the node can be found by
+	keywords?: string[];
+	experimental?: boolean;
+	deprecated?: { replacement?: string };
 
 	inputs: NodeIOType;
 	outputs: NodeIOType;
 
 	defaultHardcoded?: NodeIO;
+	// Package the node comes from, builtin nodes don't have it
+	package?: NodePackage;
+	// Long-form Markdown documentation of the node
+	docs?: string;
+	// Input with the name of the user variable, the type variable of the node is the type of the variable
+	variableInput?: string;
+	// Values of different types for the same type variable are only a warning
+	lenientTypes?: boolean;
+}
+
+export interface NodePackage {
+	name: string;
+	version: string;
+	author: string;
+	description?: string;
+	npmDependencies?: { [name: string]: string };
+}
+
+// Nodes from packages are grouped by the package first, then by their category
+export function getNodeGroup(node: NodeType): string {
+	return node.package ? `${node.package.name}: ${node.category}` : node.category;
 }
 
 export type NodeIOType = {
-	[key: string]: { type: { type: NodeConnectionType; structTags?: string[] }; name: string };
+	[key: string]: {
+		// Ports with the same type variable (generic) have the same type, until it is known their type is Any
+		type: { type: NodeConnectionType; structTags?: string[]; generic?: string };
+		name: string;
+		constraints?: InputConstraints;
+		// Input can have any number of instances, see variadicKey
+		variadic?: boolean;
+	};
 };
+
+// Variadic inputs of new nodes start with this many instances
+export const VARIADIC_START_COUNT = 2;
+
+// Key of an instance of a variadic input, instances are numbered from zero
+export function variadicKey(key: string, index: number): string {
+	return `${key}[${index}]`;
+}
+
+export function startInputCounts(type: NodeType): { [key: string]: number } {
+	return Object.fromEntries(
+		Object.entries(type.inputs)
+			.filter(([, input]) => input.variadic)
+			.map(([key]) => [key, VARIADIC_START_COUNT])
+	);
+}
+
+// Constraints of values hardcoded in inputs, the generator refuses values that don't satisfy them
+export interface InputConstraints {
+	min?: number;
+	max?: number;
+	maxLength?: number;
+	// Regex the whole text has to match
+	pattern?: string;
+	choices?: (string | number)[];
+	multiline?: boolean;
+	required?: boolean;
+}
 export type NodeIO = { [key: string]: any };
 
 export enum NodeConnectionType {
@@ -52,6 +116,8 @@ export interface ENode {
 	oPorts: { [key: string]: SVGSVGElement };
 
 	inputHardcoded: { [key: string]: any };
+	// Number of instances of the variadic inputs
+	inputCounts: { [key: string]: number };
 
 	self?: HTMLElement;
 }
@@ -81,7 +147,9 @@ export function flowToJSONParseable(flow: NodeFlow): any {
 				type: node.type.id,
 				x: node.x,
 				y: node.y,
-				inputHardcoded: node.inputHardcoded
+				inputHardcoded: node.inputHardcoded,
+				// Left out for nodes without variadic inputs, the same as the generator does
+				inputCounts: Object.keys(node.inputCounts).length ? node.inputCounts : undefined
 			};
 		}),
 		connections: flow.connections.map((conn) => {
@@ -107,8 +175,9 @@ export function flowFromProjectJSON(data: any, availableNodes: NodeType[]): Node
 		const y = node.y;
 		const uid = node.uid;
 		const inputHardcoded = node.inputHardcoded;
+		const inputCounts = node.inputCounts ?? {};
 
-		nodes.push({ type, x, y, uid, inputHardcoded, iPorts: {}, oPorts: {} });
+		nodes.push({ type, x, y, uid, inputHardcoded, inputCounts, iPorts: {}, oPorts: {} });
 	}
 
 	for (const conn of data.connections) {
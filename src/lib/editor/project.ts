@@ -13,6 +13,9 @@ import { PUBLIC_API_URL } from '$env/static/public';
 
 export const projectKey = Symbol('disbotter project');
 
+// Version of the project format, has to match FORMAT_VERSION in the generator
+export const PROJECT_FORMAT_VERSION = 2;
+
 export type ProjectContext = Writable<DisbotterProject>;
 export class DisbotterProject {
 	public name: string;
@@ -127,7 +130,8 @@ export class DisbotterProject {
 		// First, serialize all project metadata
 		const project = {
 			metadata: {
-				name: this.name
+				name: this.name,
+				formatVersion: PROJECT_FORMAT_VERSION
 			},
 			content: {
 				commands: this.commands.map((command) => command.toJSONParseable())